@@ -1,8 +1,8 @@
-use raftlog::election::{Ballot, Role};
+use raftlog::election::{Ballot, Role, Term};
 use raftlog::log::{LogIndex, LogPrefix, LogSuffix};
 use raftlog::message::Message;
 use raftlog::node::NodeId;
-use raftlog::Io;
+use raftlog::{Io, SaveBallotAndLog};
 
 use crate::io::configs::{ChannelConfig, StorageConfig, TimerConfig};
 use crate::io::futures;
@@ -106,7 +106,17 @@ impl Io for DeterministicIo {
     fn save_log_suffix(&mut self, suffix: &LogSuffix) -> Self::SaveLog {
         self.storage.save_log_suffix(suffix)
     }
+    fn save_ballot_and_log(
+        &mut self,
+        ballot: Option<Ballot>,
+        suffix: &LogSuffix,
+    ) -> SaveBallotAndLog<Self::SaveBallot, Self::SaveLog> {
+        SaveBallotAndLog::new(None, self.storage.save_ballot_and_log(ballot, suffix))
+    }
     fn load_log(&mut self, start: LogIndex, end: Option<LogIndex>) -> Self::LoadLog {
         self.storage.load_log(start, end)
     }
+    fn last_log_position(&self) -> Option<(LogIndex, Term)> {
+        self.storage.last_log_position()
+    }
 }
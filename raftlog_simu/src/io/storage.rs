@@ -1,9 +1,10 @@
 /// シミュレータ用のストレージ実装.
 ///
 /// 全てのデータはメモリ上で保持する.
-use raftlog::election::Ballot;
+use raftlog::election::{Ballot, Term};
 use raftlog::log::{Log, LogIndex, LogPosition, LogPrefix, LogSuffix};
 use raftlog::node::NodeId;
+use std::cmp;
 use trackable::error::ErrorKindExt;
 
 use crate::io::configs::StorageConfig;
@@ -78,6 +79,20 @@ impl Storage {
         DelayedResult::done(result, time)
     }
 
+    /// 投票状況とログの末尾部分を、まとめて保存する.
+    ///
+    /// 所要時間は、個別に保存した場合の長い方となる.
+    pub fn save_ballot_and_log(&mut self, ballot: Option<Ballot>, suffix: &LogSuffix) -> SaveLog {
+        let mut time =
+            self.config.save_log_entry_time.choose(&mut self.rng) * suffix.entries.len() as u64;
+        if let Some(ballot) = ballot {
+            self.ballot = ballot;
+            time = cmp::max(time, self.config.save_ballot_time.choose(&mut self.rng));
+        }
+        let result = self.log_append(suffix);
+        DelayedResult::done(result, time)
+    }
+
     /// 指定された範囲のログを読み込む.
     pub fn load_log(&mut self, start: LogIndex, end: Option<LogIndex>) -> LoadLog {
         if start < self.log_suffix.head.index {
@@ -105,6 +120,18 @@ impl Storage {
         }
     }
 
+    /// 最後のエントリのインデックスと`Term`を返す.
+    ///
+    /// ログが空の場合には`None`が返される.
+    pub fn last_log_position(&self) -> Option<(LogIndex, Term)> {
+        let tail = self.log_suffix.tail();
+        if tail.index.as_u64() == 0 {
+            None
+        } else {
+            Some((tail.index - 1, tail.prev_term))
+        }
+    }
+
     fn log_append(&mut self, suffix: &LogSuffix) -> Result<()> {
         // ローカルログと`suffix`の領域に重複部分があるかをチェック
         // (未コミット分がロールバックされる可能性もあるので、
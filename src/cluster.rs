@@ -3,9 +3,11 @@
 //! なお、クラスタ構成の動的変更に関する詳細は、
 //! [Raftの論文](https://raft.github.io/raft.pdf)の「6 Cluster membership changes」を参照のこと.
 use std::cmp;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
+use crate::log::{LogEntry, LogIndex, LogSuffix};
 use crate::node::NodeId;
+use crate::{ErrorKind, Result};
 
 /// クラスタに属するメンバ群.
 pub type ClusterMembers = BTreeSet<NodeId>;
@@ -36,6 +38,67 @@ impl ClusterState {
     }
 }
 
+/// `ClusterConfig::audit`で検出される、クラスタ構成の不変条件違反.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigViolation {
+    /// 新メンバ群が空.
+    EmptyNewMembers,
+
+    /// 安定状態にも関わらず、旧メンバ群が空ではない.
+    StableWithOldMembers,
+
+    /// 構成変更中にも関わらず、旧メンバ群が空.
+    ChangeWithoutOldMembers,
+}
+
+/// `ClusterConfig::sizing_warning`で報告される、メンバ数に関する警告.
+///
+/// メンバ数が偶数の場合には、一つ少ない奇数の場合と故障可能数が変わらないので、
+/// 余分なメンバの分だけコストが増える(かつ過半数の確保が難しくなる)だけとなる.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizingWarning {
+    /// 現在のメンバ数.
+    pub members: usize,
+
+    /// 推奨されるメンバ数(次の奇数).
+    pub suggested: usize,
+}
+
+/// `ClusterConfig::acks_required`で返される、書き込みの永続化を確認するために必要な承認数.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quorum {
+    /// 単一の構成(安定状態および`CatchUp`状態でのプライマリなメンバ群)の過半数.
+    Single(usize),
+
+    /// 新旧それぞれの構成の過半数.
+    ///
+    /// `Joint`状態では、両方の条件を同時に満たす必要がある.
+    Joint {
+        /// 新構成のメンバから必要な承認数.
+        new: usize,
+
+        /// 旧構成のメンバから必要な承認数.
+        old: usize,
+    },
+}
+
+/// 構成変更要求の識別子.
+///
+/// 利用者が構成変更要求を再送した場合に、それが同一の要求であることを識別するために使用される.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ChangeId(u64);
+impl ChangeId {
+    /// 新しい`ChangeId`インスタンスを生成する.
+    pub fn new(id: u64) -> Self {
+        ChangeId(id)
+    }
+
+    /// 識別子の値を返す.
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
 /// クラスタ構成.
 ///
 /// クラスタに属するメンバの集合に加えて、
@@ -45,6 +108,24 @@ pub struct ClusterConfig {
     new: ClusterMembers,
     old: ClusterMembers,
     state: ClusterState,
+
+    // 進行中の構成変更が提案されたログ位置(不明な場合は`None`)
+    change_index: Option<LogIndex>,
+
+    // 進行中の構成変更の要求の識別子(指定されていない場合は`None`)
+    change_id: Option<ChangeId>,
+
+    // リーダ選出におけるノード毎の優先度(指定されていないノードは`0`)
+    priorities: BTreeMap<NodeId, u32>,
+
+    // 最近取り除かれたノード群と、その構成変更が提案されたログ位置(追跡しない場合は`None`)
+    tombstones: Option<BTreeMap<NodeId, LogIndex>>,
+
+    // 新旧の構成とは別に管理される、投票権を持たずにログの複製のみを受けるメンバ群
+    learners: ClusterMembers,
+
+    // `uninitialized`で生成された構成の場合には`false`
+    bootstrapped: bool,
 }
 impl ClusterConfig {
     /// 現在のクラスタ状態を返す.
@@ -60,6 +141,14 @@ impl ClusterConfig {
         &self.new
     }
 
+    /// 構成変更の完了後にクラスタに属することになるメンバ集合を返す.
+    ///
+    /// これは状態に関わらず`new_members`と等しく、安定状態では現在のメンバ群となる.
+    /// 現時点でクラスタに属しているメンバ群(新旧の和集合)を知りたい場合には`members`を使用すること.
+    pub fn target_members(&self) -> &ClusterMembers {
+        &self.new
+    }
+
     /// 構成変更前のメンバ集合が返される.
     ///
     /// 安定状態では、空集合が返される.
@@ -83,16 +172,356 @@ impl ClusterConfig {
         }
     }
 
+    /// プライマリなメンバの内、`me`以外のもの一覧を返す.
+    ///
+    /// リーダが、自身を除いたフォロワー群との疎通状況から定足数を判定する場合や、
+    /// リーダシップの移譲先を選ぶ場合に利用できる.
+    pub fn other_voters(&self, me: &NodeId) -> Vec<NodeId> {
+        self.primary_members()
+            .iter()
+            .filter(|n| *n != me)
+            .cloned()
+            .collect()
+    }
+
+    /// 投票権を持つメンバの重みの合計を返す.
+    ///
+    /// 重み付きの投票は実装されておらず、重みを設定する手段も存在しない.
+    /// そのため各メンバの重みは常に`1`であり、返り値は投票権を持つメンバの数と等しくなる.
+    /// 投票権を持つメンバは、`Joint`状態では新旧両方の構成のメンバ、それ以外ではプライマリなメンバとなる.
+    pub fn total_weight(&self) -> u32 {
+        self.voters().len() as u32
+    }
+
+    /// 新旧それぞれの構成に属する、投票権を持つメンバの重みの合計を`(新, 旧)`の形式で返す.
+    ///
+    /// `total_weight`と同様に、各メンバの重みは常に`1`として扱われる.
+    /// 安定状態では、旧構成の重みは`0`となる.
+    /// `CatchUp`状態では、新構成にのみ属するメンバはまだ投票権を持たないので、
+    /// 新構成の重みは、旧構成にも属するメンバの分だけとなる.
+    pub fn side_weights(&self) -> (u32, u32) {
+        let voters = self.voters();
+        let weight = |members: &ClusterMembers| members.intersection(&voters).count() as u32;
+        (weight(&self.new), weight(&self.old))
+    }
+
+    /// 現在の構成において、合意形成を妨げることなく故障可能なノードの数を返す.
+    ///
+    /// 安定状態および`CatchUp`状態では、プライマリなメンバ集合の過半数を維持可能な故障数となる.
+    /// `Joint`状態では、新旧両方の構成の過半数が必要となるので、それぞれの故障可能数の小さい方が返される.
+    pub fn fault_tolerance(&self) -> usize {
+        let tolerance = |members: &ClusterMembers| members.len().saturating_sub(1) / 2;
+        match self.state {
+            ClusterState::Stable | ClusterState::CatchUp => tolerance(self.primary_members()),
+            ClusterState::Joint => cmp::min(tolerance(&self.new), tolerance(&self.old)),
+        }
+    }
+
+    /// 現在の構成において、ある書き込みが永続化された(i.e., コミットされた)と判断するために必要な承認数を返す.
+    ///
+    /// 安定状態および`CatchUp`状態では、プライマリなメンバ集合の過半数が、
+    /// `Joint`状態では、新旧それぞれの構成の過半数が必要となる.
+    /// クライアントは、各ノードの複製状況が得られる場合に、これを用いて待機すべき承認数を判断できる.
+    pub fn acks_required(&self) -> Quorum {
+        let majority = |members: &ClusterMembers| members.len() / 2 + 1;
+        match self.state {
+            ClusterState::Stable | ClusterState::CatchUp => {
+                Quorum::Single(majority(self.primary_members()))
+            }
+            ClusterState::Joint => Quorum::Joint {
+                new: majority(&self.new),
+                old: majority(&self.old),
+            },
+        }
+    }
+
+    /// プライマリなメンバ集合の要素数が偶数の場合に、その旨の警告を返す.
+    ///
+    /// 奇数の場合には`None`が返される.
+    #[allow(clippy::manual_is_multiple_of)]
+    pub fn sizing_warning(&self) -> Option<SizingWarning> {
+        let members = self.primary_members().len();
+        // NOTE: `usize::is_multiple_of`はRust 1.87以降でしか利用できないので使わない
+        if members % 2 == 0 {
+            Some(SizingWarning {
+                members,
+                suggested: members + 1,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// 合意に必要な任意の二つの過半数集合が、必ず共通のメンバを持つかどうかを判定する.
+    ///
+    /// 過半数集合同士が交差することは、一つのログ位置に対して異なるエントリがコミットされないための前提条件である.
+    /// `Joint`状態では、新旧両方の構成の過半数が要求されるので、その両方について判定を行う.
+    ///
+    /// `N`個のメンバから成る集合の過半数は`N / 2 + 1`個なので、これは常に`true`となるはずだが、
+    /// 構成変更時の自己検査として利用可能なように提供されている.
+    pub fn quorums_intersect(&self) -> bool {
+        let intersect = |members: &ClusterMembers| {
+            let quorum = members.len() / 2 + 1;
+            quorum * 2 > members.len()
+        };
+        match self.state {
+            ClusterState::Stable | ClusterState::CatchUp => intersect(self.primary_members()),
+            ClusterState::Joint => intersect(&self.new) && intersect(&self.old),
+        }
+    }
+
+    /// `n`個のメンバから成るクラスタで、`read_q`個の読み込み用の定足数と、
+    /// `write_q`個の書き込み用の定足数が、必ず共通のメンバを持つかどうかを判定する.
+    ///
+    /// Raftの書き込み用の定足数は過半数に固定されているが、独自の読み込み用の定足数を用いる場合に、
+    /// それが最新の書き込みを観測可能か(i.e., `read_q + write_q > n`)を検証するために使用する.
+    /// `n`には、通常は`primary_members().len()`を指定する.
+    pub fn quorum_overlap(read_q: usize, write_q: usize, n: usize) -> bool {
+        read_q + write_q > n
+    }
+
+    /// 二つのクラスタ構成が、投票権を持つメンバを共有しているかどうかを判定する.
+    ///
+    /// 投票権を持つメンバは、`Joint`状態では新旧両方の構成のメンバ、それ以外ではプライマリなメンバとなる.
+    ///
+    /// 共通のメンバが存在しない場合(e.g., ネットワーク分断時に、両側で強制的に構成を作り直した場合)には、
+    /// それぞれの構成が独立にリーダを選出し得るので、スプリットブレインの危険がある.
+    pub fn configs_overlap(a: &ClusterConfig, b: &ClusterConfig) -> bool {
+        !a.voters().is_disjoint(&b.voters())
+    }
+
+    /// 投票権を持つメンバ群を返す.
+    fn voters(&self) -> ClusterMembers {
+        match self.state {
+            ClusterState::Joint => self.new.union(&self.old).cloned().collect(),
+            _ => self.primary_members().clone(),
+        }
+    }
+
+    /// 投票権を持たずにログの同期のみを受けているメンバ群(学習者)を返す.
+    ///
+    /// これは`demote_to_learner`等によって降格されたメンバ群と、
+    /// `CatchUp`状態において新構成にのみ属するメンバ群の和集合である.
+    /// ただし、降格中の構成変更が完了するまでの間、投票権を持ち続けるメンバは含まれない.
+    pub fn learners(&self) -> ClusterMembers {
+        let voters = self.voters();
+        let catch_up = if self.state == ClusterState::CatchUp {
+            self.new.difference(&self.old).cloned().collect()
+        } else {
+            ClusterMembers::new()
+        };
+        self.learners
+            .union(&catch_up)
+            .filter(|n| !voters.contains(n))
+            .cloned()
+            .collect()
+    }
+
+    /// 現在のメンバ構成の読み取り専用のスナップショットを返す.
+    ///
+    /// 返り値は構成の複製なので、以後の構成変更の影響を受けない.
+    /// サービスディスカバリ等、合意処理の外部から構成を参照したい場合に使用する.
+    pub fn observe(&self) -> MembershipView {
+        MembershipView {
+            voters: self.voters(),
+            learners: self.learners(),
+            state: self.state,
+        }
+    }
+
+    /// 指定された学習者群をまとめて投票者に昇格させた構成を返す.
+    ///
+    /// 返り値は`Joint`状態となり、`nodes`は旧構成のメンバと共に投票権を持つようになる.
+    /// 一度の構成遷移で昇格が行われるので、学習者を一つずつ昇格させる場合とは異なり、
+    /// 構成変更は一回で済む.
+    ///
+    /// なお`nodes`に含まれない`CatchUp`状態の学習者は新構成から除外されるが、
+    /// 降格された学習者は、学習者のまま残る.
+    ///
+    /// # Errors
+    ///
+    /// `Joint`状態の構成に対して呼び出された場合や、
+    /// `nodes`に現在の学習者ではないものが含まれている場合には、
+    /// `ErrorKind::InvalidInput`を理由としたエラーが返される.
+    pub fn promote_learners(&self, nodes: &ClusterMembers) -> Result<Self> {
+        track_assert!(
+            self.state != ClusterState::Joint,
+            ErrorKind::InvalidInput,
+            "Cluster configuration change in progress: {:?}",
+            self
+        );
+        let learners = self.learners();
+        for node in nodes {
+            track_assert!(
+                learners.contains(node),
+                ErrorKind::InvalidInput,
+                "Not a learner: {:?}",
+                node
+            );
+        }
+        let new = self
+            .new
+            .iter()
+            .filter(|n| !learners.contains(n) || nodes.contains(n))
+            .chain(self.learners.intersection(nodes))
+            .cloned()
+            .collect();
+        let mut next = self.transit(ClusterState::Joint, new, self.primary_members().clone());
+        next.learners.retain(|n| !nodes.contains(n));
+        Ok(next)
+    }
+
+    /// 学習者`promote`の投票者への昇格と、投票者`remove`の除外を、一度の構成遷移で行った構成を返す.
+    ///
+    /// ローリングアップグレード等で、ノードを一つずつ入れ替えるために使用する.
+    /// 返り値は`Joint`状態となり、新構成の投票者群は、旧構成の`remove`を`promote`で置き換えたものとなる.
+    /// `promote_learners`と同様に、`promote`以外の学習者は新構成から除外される.
+    ///
+    /// # Errors
+    ///
+    /// `Joint`状態の構成に対して呼び出された場合や、
+    /// `promote`が現在の学習者ではない場合、`remove`が投票者ではない場合には、
+    /// `ErrorKind::InvalidInput`を理由としたエラーが返される.
+    pub fn roll(&self, promote: NodeId, remove: NodeId) -> Result<Self> {
+        track_assert!(
+            self.is_voting_member(&remove),
+            ErrorKind::InvalidInput,
+            "Not a voter: {:?}",
+            remove
+        );
+        let mut next = track!(self.promote_learners(&Some(promote).into_iter().collect()))?;
+        next.new.remove(&remove);
+        Ok(next)
+    }
+
+    /// リーダのログに追いついており、昇格の準備が整った学習者群を返す.
+    ///
+    /// `match_index`は、各メンバのローカルログに複製済みの領域の終端を返す関数で、
+    /// `leader_last`はリーダのローカルログの終端.
+    /// 返り値は`promote_learners`の入力として利用することを想定している.
+    pub fn learner_ready<F>(&self, match_index: F, leader_last: LogIndex) -> Vec<NodeId>
+    where
+        F: Fn(&NodeId) -> LogIndex,
+    {
+        self.learners()
+            .into_iter()
+            .filter(|n| match_index(n) >= leader_last)
+            .collect()
+    }
+
+    /// `node`を、クラスタから取り除くことなく、投票者から学習者に降格させる構成変更を開始する.
+    ///
+    /// 返り値は`CatchUp`状態となり、新構成は`node`を除いた投票者群となる.
+    /// `node`は学習者として記録され、`to_next_state`で構成変更が完了した後も、
+    /// 投票権を持たないメンバとしてログの複製を受け続ける.
+    /// 投票者の変更はノード一つ分のみなので、変更前後の投票者群の過半数は必ず交差する.
+    ///
+    /// # Errors
+    ///
+    /// 以下のいずれかに該当する場合には、`ErrorKind::InvalidInput`を理由としたエラーが返される:
+    ///
+    /// - 安定状態ではない(構成変更が進行中である)
+    /// - `node`が投票者ではない
+    /// - `node`を降格させると、投票者が一つも残らなくなる
+    pub fn demote_to_learner(&self, node: NodeId) -> Result<Self> {
+        track_assert!(
+            self.state.is_stable(),
+            ErrorKind::InvalidInput,
+            "Cluster configuration change in progress: {:?}",
+            self
+        );
+        let mut voters = self.new.clone();
+        track_assert!(
+            voters.remove(&node),
+            ErrorKind::InvalidInput,
+            "Not a voter: {:?}",
+            node
+        );
+        track_assert!(
+            !voters.is_empty(),
+            ErrorKind::InvalidInput,
+            "No voters would remain"
+        );
+        let mut next = self.transit(ClusterState::CatchUp, voters, self.new.clone());
+        next.learners.insert(node);
+        Ok(next)
+    }
+
     /// クラスタに属するメンバ群を返す.
     ///
     /// 構成変更中の場合には、新旧両方のメンバの和集合が返される.
+    /// 学習者も含まれるが、その場合には新旧のメンバ群の後に並ぶ.
     pub fn members(&self) -> impl Iterator<Item = &NodeId> {
-        self.new.union(&self.old)
+        let voters = self.new.union(&self.old);
+        voters.chain(
+            self.learners
+                .iter()
+                .filter(move |n| !self.new.contains(n) && !self.old.contains(n)),
+        )
+    }
+
+    /// `primary_members`の複製を、IDの昇順に並べて返す.
+    ///
+    /// 呼び出し元で、構成を借用したままにせずに投票者群を走査したい場合に使用する.
+    pub fn voter_ids(&self) -> Vec<NodeId> {
+        self.primary_members().iter().cloned().collect()
+    }
+
+    /// クラスタに属するメンバ群を、IDの昇順に並べて返す.
+    ///
+    /// 構成変更中の場合には、新旧両方のメンバの和集合が返される.
+    ///
+    /// 順序は`NodeId`の`Ord`実装に従い、常に同じ構成に対しては同じ結果となる.
+    /// ただし、これはIDの文字列の辞書順であって、ID中の数値の大小順ではないので注意が必要
+    /// (e.g., `"node10"`は`"node2"`よりも前に並ぶ).
+    pub fn sorted_members(&self) -> Vec<NodeId> {
+        self.members()
+            .cloned()
+            .collect::<ClusterMembers>()
+            .into_iter()
+            .collect()
+    }
+
+    /// クライアントの要求の送信先となるメンバ群を返す.
+    ///
+    /// 構成変更中の場合には、新旧両方のメンバの和集合が返されるが、
+    /// 変更後も残る`new`のメンバが先に、取り除かれる予定の旧メンバが後に並ぶ.
+    /// それぞれの中ではIDの昇順となる.
+    pub fn routable_members(&self) -> Vec<NodeId> {
+        self.new
+            .iter()
+            .chain(self.old.difference(&self.new))
+            .cloned()
+            .collect()
+    }
+
+    /// 構成の状態を無視して、`other`と同じメンバ群から構成されているかどうかを判定する.
+    ///
+    /// 比較には、新旧両方のメンバの和集合が用いられる.
+    pub fn same_effective_membership(&self, other: &ClusterConfig) -> bool {
+        self.members().eq(other.members())
     }
 
     /// このクラスタ構成に含まれるノードかどうかを判定する.
     pub fn is_known_node(&self, node: &NodeId) -> bool {
-        self.new.contains(node) || self.old.contains(node)
+        self.new.contains(node) || self.old.contains(node) || self.learners.contains(node)
+    }
+
+    /// `node`を構成変更後のメンバ集合から取り除くことが可能かどうかを判定する.
+    ///
+    /// `node`が`target_members`に含まれていない場合や、
+    /// 取り除くとメンバが一つも残らなくなる場合には`false`が返される.
+    pub fn is_removable(&self, node: &NodeId) -> bool {
+        self.new.contains(node) && self.new.len() > 1
+    }
+
+    /// ノード`me`が、自身を停止させるべきかどうかを判定する.
+    ///
+    /// `me`を取り除く構成変更が完了(i.e., 安定状態に遷移)しており、
+    /// `me`が構成に(学習者としても)含まれていない場合には`true`が返される.
+    /// 構成変更の進行中は、`me`が新構成に含まれていなくても`false`が返される.
+    pub fn should_shutdown(&self, me: &NodeId) -> bool {
+        self.state.is_stable() && !self.is_known_node(me)
     }
 
     /// 新しい安定状態の`ClusterConfig`インスタンスを生成する.
@@ -101,9 +530,54 @@ impl ClusterConfig {
             new: members,
             old: ClusterMembers::default(),
             state: ClusterState::Stable,
+            change_index: None,
+            change_id: None,
+            priorities: BTreeMap::new(),
+            tombstones: None,
+            learners: ClusterMembers::new(),
+            bootstrapped: true,
+        }
+    }
+
+    /// まだどのクラスタにも参加していないことを示す、未初期化の`ClusterConfig`インスタンスを生成する.
+    ///
+    /// 未初期化の構成はメンバを一つも持たず、`is_bootstrapped`は`false`を返す.
+    /// 合意値の計算(e.g., `committable_index`)はエラーとなる.
+    pub fn uninitialized() -> Self {
+        ClusterConfig {
+            bootstrapped: false,
+            ..Self::new(ClusterMembers::new())
         }
     }
 
+    /// クラスタの構成が初期化済みかどうかを判定する.
+    ///
+    /// `uninitialized`で生成された構成(およびそこから状態遷移したもの)に対してのみ`false`が返される.
+    /// メンバ集合が空かどうかは判定に影響しない.
+    pub fn is_bootstrapped(&self) -> bool {
+        self.bootstrapped
+    }
+
+    /// 任意のノードID列から、新しい安定状態の`ClusterConfig`インスタンスを生成する.
+    ///
+    /// 重複したIDは一つにまとめられる.
+    ///
+    /// # Errors
+    ///
+    /// `members`が空の場合には、`ErrorKind::InvalidInput`を理由としたエラーが返される.
+    pub fn from_members_iter<I>(members: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = NodeId>,
+    {
+        let members: ClusterMembers = members.into_iter().collect();
+        track_assert!(
+            !members.is_empty(),
+            ErrorKind::InvalidInput,
+            "Empty members"
+        );
+        Ok(Self::new(members))
+    }
+
     /// 構成変更中の`ClusterConfig`インスタンスを生成する.
     pub fn with_state(
         new_members: ClusterMembers,
@@ -114,88 +588,825 @@ impl ClusterConfig {
             new: new_members,
             old: old_members,
             state,
+            change_index: None,
+            change_id: None,
+            priorities: BTreeMap::new(),
+            tombstones: None,
+            learners: ClusterMembers::new(),
+            bootstrapped: true,
         }
     }
 
-    /// 構成変更を開始するために、`new`を構成変更後のメンバ群として設定し、
-    /// `CatchUp`状態に遷移した`ClusterConfig`インスタンスを返す.
-    pub(crate) fn start_config_change(&self, new: ClusterMembers) -> Self {
-        ClusterConfig {
-            new,
-            old: self.primary_members().clone(),
-            state: ClusterState::CatchUp,
+    /// 進行中の構成変更が提案されたログ位置を設定する.
+    ///
+    /// 安定状態の構成に対して設定した値は無視される.
+    pub fn with_change_index(mut self, index: LogIndex) -> Self {
+        if self.is_change_in_flight() {
+            self.change_index = Some(index);
         }
+        self
     }
 
-    /// 次の状態に遷移する.
+    /// 進行中の構成変更が提案されたログ位置を返す.
     ///
-    /// # 状態遷移表
+    /// 安定状態の場合や、提案位置が記録されていない場合には`None`が返される.
+    pub fn change_index(&self) -> Option<LogIndex> {
+        self.change_index
+    }
+
+    /// 進行中の構成変更の要求の識別子を設定する.
     ///
-    /// - `Stable` => `Stable`
-    /// - `CatchUp` => `Joint`
-    /// - `Joint` => `Stable`
-    pub(crate) fn to_next_state(&self) -> Self {
-        match self.state {
-            ClusterState::Stable => self.clone(),
-            ClusterState::CatchUp => {
-                let mut next = self.clone();
-                next.state = ClusterState::Joint;
-                next
-            }
-            ClusterState::Joint => {
-                let mut next = self.clone();
-                next.old = ClusterMembers::new();
-                next.state = ClusterState::Stable;
-                next
-            }
+    /// 安定状態の構成に対して設定した値は無視される.
+    pub fn with_change_id(mut self, id: ChangeId) -> Self {
+        if self.is_change_in_flight() {
+            self.change_id = Some(id);
         }
+        self
     }
 
-    /// 現在の構成での最新の合意値を返す.
-    //
-    /// `f`は、各メンバの現在の承認値を返す関数.
-    /// あるメンバが、仮にXという値を返したとして場合、
-    /// それよりも小さな任意の値yに関しても、
-    /// 承認済みのものとして扱われる.
+    /// 進行中の構成変更の要求の識別子を返す.
     ///
-    /// 最終的な合意値は「メンバの過半数が承認した値集合の中で
-    /// 最も大きな値」となる.
-    pub(crate) fn consensus_value<F, T>(&self, f: F) -> T
+    /// 安定状態の場合や、識別子が指定されていない場合には`None`が返される.
+    pub fn change_id(&self) -> Option<ChangeId> {
+        self.change_id
+    }
+
+    /// 進行中の構成変更が、識別子が`id`で、新メンバ群が`new`の要求によるものかどうかを判定する.
+    ///
+    /// 再送された構成変更要求を、新しい構成変更として扱わないようにするために使用する.
+    pub fn is_same_change(&self, id: ChangeId, new: &ClusterMembers) -> bool {
+        self.is_change_in_flight() && self.change_id == Some(id) && self.new == *new
+    }
+
+    /// リーダ選出におけるノード毎の優先度を設定する.
+    ///
+    /// 優先度の値が大きいノードほど、リーダに選出されやすくなる(`should_defer_election`参照).
+    /// `priorities`に含まれないノードの優先度は`0`となる.
+    pub fn with_priorities(mut self, priorities: BTreeMap<NodeId, u32>) -> Self {
+        self.priorities = priorities;
+        self
+    }
+
+    /// `with_priorities`で設定された、ノード毎の優先度を返す.
+    pub fn priorities(&self) -> &BTreeMap<NodeId, u32> {
+        &self.priorities
+    }
+
+    /// 新旧の構成とは別に管理される学習者群を設定する.
+    ///
+    /// 通常は`demote_to_learner`によって設定されるので、
+    /// これは主に、符号化された構成を復元する場合に使用される.
+    pub fn with_learners(mut self, learners: ClusterMembers) -> Self {
+        self.learners = learners;
+        self
+    }
+
+    /// 新旧の構成とは別に管理される学習者群を返す.
+    ///
+    /// `learners`とは異なり、`CatchUp`状態において新構成にのみ属するメンバ群は含まれず、
+    /// 降格中で投票権を持ち続けているメンバは含まれる.
+    pub fn explicit_learners(&self) -> &ClusterMembers {
+        &self.learners
+    }
+
+    /// 取り除かれたノード群を、墓標として追跡するようにする.
+    ///
+    /// `tombstones`は、初期状態の墓標群(キーはノードIDで、値はそのノードを取り除く構成変更が提案されたログ位置).
+    /// 以後`Joint`状態から安定状態に遷移する度に、旧構成にのみ属していたノード群が墓標に追加される.
+    /// 提案位置が記録されていない場合には、ログ位置は`0`として扱われる.
+    ///
+    /// 取り除かれたノードから遅れて届いたメッセージを、通信層で破棄するために利用できる.
+    pub fn with_tombstones(mut self, tombstones: BTreeMap<NodeId, LogIndex>) -> Self {
+        self.tombstones = Some(tombstones);
+        self
+    }
+
+    /// 墓標群を返す.
+    ///
+    /// 墓標を追跡していない場合には`None`が返される.
+    pub fn tombstones(&self) -> Option<&BTreeMap<NodeId, LogIndex>> {
+        self.tombstones.as_ref()
+    }
+
+    /// `node`が最近取り除かれたノードとして、墓標に記録されているかどうかを判定する.
+    pub fn is_tombstoned(&self, node: &NodeId) -> bool {
+        self.tombstones
+            .as_ref()
+            .is_some_and(|tombstones| tombstones.contains_key(node))
+    }
+
+    /// 取り除かれたログ位置が`before`よりも前の墓標を破棄した構成を返す.
+    ///
+    /// 十分に古い墓標は、そのノードからのメッセージが既に届かなくなっているものとして、回収することができる.
+    pub fn prune_tombstones(mut self, before: LogIndex) -> Self {
+        if let Some(ref mut tombstones) = self.tombstones {
+            tombstones.retain(|_, removed_at| *removed_at >= before);
+        }
+        self
+    }
+
+    /// `node`のリーダ選出における優先度を返す.
+    pub fn priority(&self, node: &NodeId) -> u32 {
+        self.priorities.get(node).cloned().unwrap_or(0)
+    }
+
+    /// ノード`me`が、立候補を遅らせるべきかどうかを判定する.
+    ///
+    /// `me`よりも優先度の高い投票者が存在する場合には`true`が返される.
+    /// その場合、`me`は立候補を遅らせることで、そのノードにリーダとなる機会を譲ることが望ましい.
+    /// なお、これは選挙の安全性には影響せず、どのノードがリーダとなるかの傾向を変えるだけである.
+    pub fn should_defer_election(&self, me: &NodeId) -> bool {
+        let mine = self.priority(me);
+        self.voters()
+            .iter()
+            .any(|n| n != me && self.priority(n) > mine)
+    }
+
+    /// `node`が投票権を持つメンバかどうかを判定する.
+    ///
+    /// `CatchUp`状態の新構成にのみ属するメンバ(学習者)は、投票権を持たない.
+    pub fn is_voting_member(&self, node: &NodeId) -> bool {
+        self.voters().contains(node)
+    }
+
+    /// ノード`me`が、リーダに立候補する資格を持つかどうかを判定する.
+    ///
+    /// `is_up_to_date`は、`me`のログが過半数のメンバのログ以上に新しいかどうかを返す関数で、
+    /// `me`が投票権を持つメンバである場合にのみ呼び出される.
+    pub fn is_leader_eligible<F>(&self, me: &NodeId, is_up_to_date: F) -> bool
     where
-        F: Fn(&NodeId) -> T,
-        T: Ord + Copy + Default,
+        F: FnOnce(&NodeId) -> bool,
     {
-        match self.state {
-            ClusterState::Stable => median(&self.new, &f),
-            ClusterState::CatchUp => median(&self.old, &f),
-            ClusterState::Joint => {
-                // joint consensus
-                cmp::min(median(&self.new, &f), median(&self.old, &f))
-            }
+        self.is_voting_member(me) && is_up_to_date(me)
+    }
+
+    /// メンバ`node`との同期に、スナップショットの転送が必要かどうかを判定する.
+    ///
+    /// `node_match`は`node`のログの同期済みの位置、`log_start`はリーダのログ上で
+    /// 参照可能な最初のエントリの位置(i.e., スナップショットに含まれない最初のエントリの位置).
+    ///
+    /// 同期に必要なエントリ群が既にスナップショットに取り込まれている場合に`true`が返される.
+    /// `node`がメンバではない場合には、同期の対象外なので常に`false`が返される.
+    pub fn needs_snapshot(&self, node: &NodeId, node_match: LogIndex, log_start: LogIndex) -> bool {
+        self.is_known_node(node) && node_match < log_start
+    }
+
+    /// 進行中の構成変更で`node`が追加される場合に、その変更が提案されたログ位置を返す.
+    ///
+    /// 構成変更の完了後(i.e., 安定状態)には`None`が返される.
+    pub fn member_added_at(&self, node: &NodeId) -> Option<LogIndex> {
+        if self.new.contains(node) && !self.old.contains(node) {
+            self.change_index
+        } else {
+            None
         }
     }
 
-    /// 基本的には`consensus_value`メソッドと同様.
+    /// 進行中の構成変更で`node`が取り除かれる場合に、その変更が提案されたログ位置を返す.
     ///
-    /// ただし構成変更中には、常に新旧メンバ群の両方から、
-    /// 過半数の承認を要求するところが異なる.
-    pub(crate) fn full_consensus_value<F, T>(&self, f: F) -> T
+    /// 構成変更の完了後(i.e., 安定状態)には`None`が返される.
+    pub fn member_removed_at(&self, node: &NodeId) -> Option<LogIndex> {
+        if self.old.contains(node) && !self.new.contains(node) {
+            self.change_index
+        } else {
+            None
+        }
+    }
+
+    /// `suffix`に含まれる構成変更エントリ群を、ログ上の出現順に抽出して返す.
+    ///
+    /// 返り値の各要素は「エントリのインデックス」と「そのエントリが保持するクラスタ構成」の組.
+    pub fn config_history(suffix: &LogSuffix) -> Vec<(LogIndex, ClusterConfig)> {
+        (suffix.head.index.as_u64()..)
+            .map(LogIndex::new)
+            .zip(suffix.entries.iter())
+            .filter_map(|(index, entry)| {
+                if let LogEntry::Config { ref config, .. } = *entry {
+                    Some((index, config.clone()))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// この構成が、`suffix`に含まれる最後の構成変更エントリと一致するかどうかを検査する.
+    ///
+    /// `suffix`に構成変更エントリが含まれない場合には、検査は常に成功する.
+    ///
+    /// # Errors
+    ///
+    /// 両者が一致しない場合には、`ErrorKind::InconsistentState`を理由としたエラーが返される.
+    /// これはローカルログの破損や、実装上のバグを示唆する.
+    pub fn validate_against_log(&self, suffix: &LogSuffix) -> Result<()> {
+        if let Some((index, config)) = Self::config_history(suffix).pop() {
+            track_assert_eq!(
+                *self,
+                config,
+                ErrorKind::InconsistentState,
+                "Last config entry in the log: index={:?}",
+                index
+            );
+        }
+        Ok(())
+    }
+
+    /// ログのエントリ群に含まれる構成変更エントリを順に適用して、最終的なクラスタ構成を構築する.
+    ///
+    /// ノードの再起動時に、ログのみからクラスタ構成を復元するために使用する.
+    /// 構成変更以外のエントリは無視される.
+    ///
+    /// 最初の構成変更エントリの構成は、そのまま初期構成として採用される.
+    /// それ以降の構成は、直前の構成からの正当な遷移(i.e., 同一構成の再提案、
+    /// 安定状態からの構成変更の開始、ないし`to_next_state`による遷移)である必要がある.
+    ///
+    /// # Errors
+    ///
+    /// 構成変更エントリが含まれない場合や、不正な遷移が含まれる場合には、
+    /// `ErrorKind::InvalidInput`を理由としたエラーが返される.
+    pub fn replay<'a, I>(entries: I) -> Result<Self>
     where
-        F: Fn(&NodeId) -> T,
-        T: Ord + Copy + Default,
+        I: IntoIterator<Item = &'a LogEntry>,
     {
-        if self.state.is_stable() {
-            median(&self.new, &f)
-        } else {
-            // joint consensus
-            cmp::min(median(&self.new, &f), median(&self.old, &f))
+        let mut current: Option<ClusterConfig> = None;
+        for entry in entries {
+            let config = match *entry {
+                LogEntry::Config { ref config, .. } => config,
+                _ => continue,
+            };
+            if let Some(ref prev) = current {
+                track_assert!(
+                    prev.can_transit_to(config),
+                    ErrorKind::InvalidInput,
+                    "Illegal transition: prev={:?}, next={:?}",
+                    prev,
+                    config
+                );
+            }
+            current = Some(config.clone());
+        }
+        match current {
+            Some(config) => Ok(config),
+            None => track_panic!(ErrorKind::InvalidInput, "No config entries"),
         }
     }
-}
 
-fn median<F, T>(members: &ClusterMembers, f: F) -> T
-where
-    F: Fn(&NodeId) -> T,
+    fn can_transit_to(&self, next: &ClusterConfig) -> bool {
+        let same = |a: &ClusterConfig, b: &ClusterConfig| {
+            a.state == b.state && a.new == b.new && a.old == b.old
+        };
+        same(self, next)
+            || same(&self.to_next_state(), next)
+            || (self.state.is_stable()
+                && next.state == ClusterState::CatchUp
+                && next.old == self.new)
+    }
+
+    /// 組み込み用途向けに、メンバ構成を小さなバイト列に符号化する.
+    ///
+    /// 各ノードIDは、`dictionary`内での位置(一バイト)に置き換えられる.
+    /// 符号化結果の形式は以下の通り:
+    ///
+    /// - 一バイト目: 上位2ビットが状態(`0`: `Stable`, `1`: `CatchUp`, `2`: `Joint`)、下位6ビットが新メンバ数
+    /// - 新メンバ群の位置の列
+    /// - 旧メンバ数(一バイト)
+    /// - 旧メンバ群の位置の列
+    ///
+    /// そのため、符号化結果の長さは常に`2 + new_members().len() + old_members().len()`バイトとなる.
+    ///
+    /// なお、符号化されるのはメンバ群と状態のみであり、
+    /// 構成変更の識別子や優先度、学習者等の付随情報は含まれない.
+    ///
+    /// # Errors
+    ///
+    /// 以下の場合には、`ErrorKind::InvalidInput`を理由としたエラーが返される:
+    ///
+    /// - `dictionary`に含まれていない、ないし256番目以降に位置するメンバが存在する
+    /// - 新メンバ数が`63`を、旧メンバ数が`255`を超えている
+    pub fn encode_compact(&self, dictionary: &[NodeId]) -> Result<Vec<u8>> {
+        track_assert!(
+            self.new.len() < 1 << 6,
+            ErrorKind::InvalidInput,
+            "Too many members: {}",
+            self.new.len()
+        );
+        track_assert!(
+            self.old.len() <= usize::from(u8::MAX),
+            ErrorKind::InvalidInput,
+            "Too many old members: {}",
+            self.old.len()
+        );
+        let tag = match self.state {
+            ClusterState::Stable => 0,
+            ClusterState::CatchUp => 1,
+            ClusterState::Joint => 2,
+        };
+        let mut bytes = Vec::with_capacity(2 + self.new.len() + self.old.len());
+        bytes.push(tag << 6 | self.new.len() as u8);
+        track!(encode_indices(&mut bytes, &self.new, dictionary))?;
+        bytes.push(self.old.len() as u8);
+        track!(encode_indices(&mut bytes, &self.old, dictionary))?;
+        Ok(bytes)
+    }
+
+    /// `encode_compact`で符号化されたバイト列から、`ClusterConfig`インスタンスを復元する.
+    ///
+    /// `dictionary`には、符号化時と同じものを指定する必要がある.
+    ///
+    /// # Errors
+    ///
+    /// `bytes`が不正な形式の場合(e.g., 安定状態なのに旧メンバを含む)や、
+    /// `dictionary`の範囲外の位置を含む場合には、
+    /// `ErrorKind::InvalidInput`を理由としたエラーが返される.
+    pub fn decode_compact(bytes: &[u8], dictionary: &[NodeId]) -> Result<Self> {
+        let (&header, rest) =
+            track_assert_some!(bytes.split_first(), ErrorKind::InvalidInput, "Empty input");
+        let state = match header >> 6 {
+            0 => ClusterState::Stable,
+            1 => ClusterState::CatchUp,
+            2 => ClusterState::Joint,
+            tag => track_panic!(ErrorKind::InvalidInput, "Unknown state tag: {}", tag),
+        };
+        let (new, rest) = track!(decode_indices(rest, usize::from(header & 0x3F), dictionary))?;
+        let (&old_len, rest) = track_assert_some!(
+            rest.split_first(),
+            ErrorKind::InvalidInput,
+            "Truncated input"
+        );
+        let (old, rest) = track!(decode_indices(rest, usize::from(old_len), dictionary))?;
+        track_assert!(
+            rest.is_empty(),
+            ErrorKind::InvalidInput,
+            "Trailing bytes: {}",
+            rest.len()
+        );
+        track_assert!(
+            !state.is_stable() || old.is_empty(),
+            ErrorKind::InvalidInput,
+            "Stable config with old members: {:?}",
+            old
+        );
+        Ok(Self::with_state(new, old, state))
+    }
+
+    /// 構成変更が進行中(i.e., `CatchUp`ないし`Joint`状態)の場合には`true`を返す.
+    pub fn is_change_in_flight(&self) -> bool {
+        !self.state.is_stable()
+    }
+
+    /// 構成変更を開始するために、`new`を構成変更後のメンバ群として設定し、
+    /// `CatchUp`状態に遷移した`ClusterConfig`インスタンスを返す.
+    pub(crate) fn start_config_change(&self, new: ClusterMembers) -> Self {
+        let old = self.primary_members().clone();
+        let mut next = self.transit(ClusterState::CatchUp, new, old);
+        // 再び追加されるノードは、墓標から取り除く
+        if let Some(ref mut tombstones) = next.tombstones {
+            let new = &next.new;
+            tombstones.retain(|n, _| !new.contains(n));
+        }
+        // 新構成に含まれる学習者は、投票者に昇格する
+        let new = &next.new;
+        next.learners.retain(|n| !new.contains(n));
+        next
+    }
+
+    /// 構成変更を開始する.
+    ///
+    /// `start_config_change`と同様だが、`single_node_only`が`true`の場合には、
+    /// 一度に変更可能なメンバをノード一つ分(i.e., 一つの追加ないし削除)に制限する.
+    ///
+    /// # Errors
+    ///
+    /// 既に構成変更が進行中の場合には、`ErrorKind::Busy`を理由としたエラーが返される.
+    ///
+    /// `new`が空の場合や、`single_node_only`が`true`で、かつ
+    /// 二つ以上のメンバが変更される場合には、`ErrorKind::InvalidInput`を理由としたエラーが返される.
+    pub fn start_config_change_checked(
+        &self,
+        new: ClusterMembers,
+        single_node_only: bool,
+    ) -> Result<Self> {
+        track_assert!(
+            !self.is_change_in_flight(),
+            ErrorKind::Busy,
+            "Cluster configuration change in progress: {:?}",
+            self
+        );
+        track_assert!(!new.is_empty(), ErrorKind::InvalidInput, "Empty members");
+        let next = self.start_config_change(new);
+        track_assert!(
+            !single_node_only || next.is_single_node_change(),
+            ErrorKind::InvalidInput,
+            "Multi-node change: old={:?}, new={:?}",
+            next.old,
+            next.new
+        );
+        Ok(next)
+    }
+
+    /// `start_config_change_checked`と同様だが、新旧の構成に共通するメンバが存在しない構成変更を拒否する.
+    ///
+    /// そのような構成変更は理論上は可能だが、新旧それぞれの過半数を橋渡しするノードが存在しないため、
+    /// より安全側に倒したい場合に使用する.
+    ///
+    /// # Errors
+    ///
+    /// `is_disjoint_change`が`true`を返す構成変更に対しては、
+    /// `ErrorKind::InvalidInput`を理由としたエラーが返される.
+    /// その場合、利用者は新旧の構成の両方に属するノードが残るように、構成変更を複数回に分けて実施すべきである.
+    ///
+    /// それ以外のエラーは`start_config_change_checked`と同様.
+    pub fn start_config_change_strict(&self, new: ClusterMembers) -> Result<Self> {
+        let next = track!(self.start_config_change_checked(new, false))?;
+        track_assert!(
+            !self.is_disjoint_change(&next.new),
+            ErrorKind::InvalidInput,
+            "Disjoint config change: old={:?}, new={:?}",
+            next.old,
+            next.new
+        );
+        Ok(next)
+    }
+
+    /// この構成から`proposed_new`への構成変更で、新旧の構成に共通するメンバが存在しないかどうかを判定する.
+    pub fn is_disjoint_change(&self, proposed_new: &ClusterMembers) -> bool {
+        self.primary_members().is_disjoint(proposed_new)
+    }
+
+    /// この構成から`proposed_new`への構成変更によって、現在のリーダ`leader`が取り除かれるかどうかを判定する.
+    ///
+    /// `true`の場合には、リーダは構成変更のコミット後に、自ら降格する必要がある.
+    /// それまでの間は、リーダは(新構成のメンバではなくとも)ログの複製を継続する.
+    pub fn change_removes_leader(&self, proposed_new: &ClusterMembers, leader: &NodeId) -> bool {
+        self.primary_members().contains(leader) && !proposed_new.contains(leader)
+    }
+
+    /// 進行中の構成変更で変更されるメンバが、ちょうどノード一つ分かどうかを判定する.
+    ///
+    /// 安定状態では`false`が返される.
+    pub fn is_single_node_change(&self) -> bool {
+        self.is_change_in_flight() && self.new.symmetric_difference(&self.old).count() == 1
+    }
+
+    /// 次の状態に遷移する.
+    ///
+    /// # 状態遷移表
+    ///
+    /// - `Stable` => `Stable`
+    /// - `CatchUp` => `Joint`
+    /// - `Joint` => `Stable`
+    pub(crate) fn to_next_state(&self) -> Self {
+        match self.state {
+            ClusterState::Stable => self.clone(),
+            ClusterState::CatchUp => {
+                self.transit(ClusterState::Joint, self.new.clone(), self.old.clone())
+            }
+            ClusterState::Joint => {
+                // 破棄される旧メンバ群は複製しない
+                let mut next = self.transit(
+                    ClusterState::Stable,
+                    self.new.clone(),
+                    ClusterMembers::new(),
+                );
+                if let Some(ref mut tombstones) = next.tombstones {
+                    let removed_at = self.change_index.unwrap_or_default();
+                    for node in self.old.difference(&self.new) {
+                        tombstones.insert(node.clone(), removed_at);
+                    }
+                }
+                next
+            }
+        }
+    }
+
+    /// `state`状態で、新旧のメンバ群がそれぞれ`new`と`old`の構成を返す.
+    ///
+    /// 構成の状態遷移は全てこのメソッドを経由する.
+    /// 優先度や墓標、学習者といった付随情報は常に引き継がれるが、
+    /// 構成変更に固有の情報(`change_index`等)は、構成変更中の状態同士の遷移でのみ引き継がれる.
+    ///
+    /// メンバ群は呼び出し元で用意されるので、変更されない側の集合のみを複製すれば良い.
+    fn transit(&self, state: ClusterState, new: ClusterMembers, old: ClusterMembers) -> Self {
+        let keep_change = self.is_change_in_flight() && !state.is_stable();
+        ClusterConfig {
+            new,
+            old,
+            state,
+            change_index: self.change_index.filter(|_| keep_change),
+            change_id: self.change_id.filter(|_| keep_change),
+            priorities: self.priorities.clone(),
+            tombstones: self.tombstones.clone(),
+            learners: self.learners.clone(),
+            bootstrapped: self.bootstrapped,
+        }
+    }
+
+    /// クラスタ構成の不変条件を検査して、違反しているもの一覧を返す.
+    ///
+    /// 構成が妥当な場合には、空のリストが返される.
+    pub fn audit(&self) -> Vec<ConfigViolation> {
+        let mut violations = Vec::new();
+        if self.new.is_empty() {
+            violations.push(ConfigViolation::EmptyNewMembers);
+        }
+        if self.state.is_stable() {
+            if !self.old.is_empty() {
+                violations.push(ConfigViolation::StableWithOldMembers);
+            }
+        } else if self.old.is_empty() {
+            violations.push(ConfigViolation::ChangeWithoutOldMembers);
+        }
+        violations
+    }
+
+    /// 構成変更の次の状態に遷移する.
+    ///
+    /// # 状態遷移表
+    ///
+    /// - `CatchUp` => `Joint`
+    /// - `Joint` => `Stable`
+    ///
+    /// # Errors
+    ///
+    /// 安定状態で呼び出された場合には、`ErrorKind::InvalidInput`を理由としたエラーが返される.
+    pub fn to_next_state_checked(&self) -> Result<Self> {
+        track_assert!(
+            self.is_change_in_flight(),
+            ErrorKind::InvalidInput,
+            "No configuration change in progress"
+        );
+        Ok(self.to_next_state())
+    }
+
+    /// 安定状態の構成から、`target`をメンバとする安定状態の構成に移行するまでに、
+    /// 経由する構成の列(`CatchUp`, `Joint`, `Stable`)を返す.
+    ///
+    /// # Errors
+    ///
+    /// 既に構成変更が進行中の場合には、`ErrorKind::Busy`を理由としたエラーが返される.
+    ///
+    /// `target`が空の場合には、`ErrorKind::InvalidInput`を理由としたエラーが返される.
+    pub fn stage_to(&self, target: ClusterMembers) -> Result<Vec<Self>> {
+        track_assert!(
+            !self.is_change_in_flight(),
+            ErrorKind::Busy,
+            "Cluster configuration change in progress: {:?}",
+            self
+        );
+        track_assert!(!target.is_empty(), ErrorKind::InvalidInput, "Empty target");
+        let catch_up = self.start_config_change(target);
+        let joint = catch_up.to_next_state();
+        let stable = joint.to_next_state();
+        Ok(vec![catch_up, joint, stable])
+    }
+
+    /// 新構成にのみ属するメンバ群の全てが、リーダのログに追いついているかどうかを判定する.
+    ///
+    /// `match_index`は、各メンバのログの同期済みの位置を返す関数で、
+    /// 新構成にのみ属するメンバに対してのみ呼び出される.
+    /// 全ての呼び出し結果が`leader_last`以上であれば`true`が返される.
+    ///
+    /// 安定状態では、追いつく必要のあるメンバは存在しないので、常に`true`が返される.
+    /// `enter_joint`に渡す値を求めるために利用できる.
+    pub fn new_members_caught_up<F>(&self, match_index: F, leader_last: LogIndex) -> bool
+    where
+        F: Fn(&NodeId) -> LogIndex,
+    {
+        self.state.is_stable()
+            || self
+                .new
+                .difference(&self.old)
+                .all(|n| match_index(n) >= leader_last)
+    }
+
+    /// `CatchUp`状態から`Joint`状態に遷移する.
+    ///
+    /// `new_caught_up`には、新構成のみに属するメンバ群のログが、リーダに追いついているかどうかを指定する.
+    ///
+    /// # Errors
+    ///
+    /// `new_caught_up`が`false`の場合には、`ErrorKind::Busy`を理由としたエラーが返される.
+    ///
+    /// `CatchUp`以外の状態で呼び出された場合には、`ErrorKind::InvalidInput`を理由としたエラーが返される.
+    pub fn enter_joint(&self, new_caught_up: bool) -> Result<Self> {
+        track_assert_eq!(self.state, ClusterState::CatchUp, ErrorKind::InvalidInput);
+        track_assert!(
+            new_caught_up,
+            ErrorKind::Busy,
+            "New members have not caught up yet"
+        );
+        Ok(self.to_next_state())
+    }
+
+    /// コミット済みの位置の進行に合わせて、構成変更を次の状態に進める.
+    ///
+    /// コミット済みの位置が更新される度に呼び出されることを想定している.
+    ///
+    /// - `committed`: コミット済みの最後のエントリのインデックス
+    /// - `joint_entry_index`: `Joint`状態の構成エントリが追記されたインデックス(未追記なら`None`)
+    /// - `catchup_done`: 新構成にのみ属するメンバ群が、リーダのログに追いついているかどうか
+    ///
+    /// # 状態遷移表
+    ///
+    /// - `CatchUp` => `Joint` (`catchup_done`の場合)
+    /// - `Joint` => `Stable` (`joint_entry_index`がコミット済みの場合)
+    ///
+    /// 遷移条件を満たさない場合や安定状態の場合には、現在の構成がそのまま返される.
+    /// 一度の呼び出しで遷移するのは、一段階のみ.
+    pub fn on_commit_advanced(
+        &self,
+        committed: LogIndex,
+        joint_entry_index: Option<LogIndex>,
+        catchup_done: bool,
+    ) -> Self {
+        let advance = match self.state {
+            ClusterState::Stable => false,
+            ClusterState::CatchUp => catchup_done,
+            ClusterState::Joint => joint_entry_index.is_some_and(|i| i <= committed),
+        };
+        if advance {
+            self.to_next_state()
+        } else {
+            self.clone()
+        }
+    }
+
+    /// 現在の構成での最新の合意値を返す.
+    //
+    /// `f`は、各メンバの現在の承認値を返す関数.
+    /// あるメンバが、仮にXという値を返したとして場合、
+    /// それよりも小さな任意の値yに関しても、
+    /// 承認済みのものとして扱われる.
+    ///
+    /// 最終的な合意値は「メンバの過半数が承認した値集合の中で
+    /// 最も大きな値」となる.
+    ///
+    /// # Errors
+    ///
+    /// 未初期化の構成(`is_bootstrapped`参照)に対して呼び出された場合には、
+    /// `ErrorKind::InvalidInput`を理由としたエラーが返される.
+    pub(crate) fn consensus_value<F, T>(&self, f: F) -> Result<T>
+    where
+        F: Fn(&NodeId) -> T,
+        T: Ord + Copy + Default,
+    {
+        track!(self.ensure_bootstrapped())?;
+        Ok(match self.state {
+            ClusterState::Stable => median(&self.new, &f),
+            ClusterState::CatchUp => median(&self.old, &f),
+            ClusterState::Joint => {
+                // joint consensus
+                cmp::min(median(&self.new, &f), median(&self.old, &f))
+            }
+        })
+    }
+
+    /// `consensus_value`と同様に合意値を求め、その値と、それを承認しているメンバの数を返す.
+    ///
+    /// 承認しているメンバとは、合意値の決定に関与するメンバ群
+    /// (`Joint`状態では新旧両方のメンバ群)の内で、合意値以上の値を返したもののこと.
+    /// ある値がコミットされた(あるいはされなかった)理由を調査するために利用できる.
+    ///
+    /// # Errors
+    ///
+    /// 未初期化の構成に対して呼び出された場合には、
+    /// `ErrorKind::InvalidInput`を理由としたエラーが返される.
+    pub fn consensus_value_with_support<F, T>(&self, f: F) -> Result<(T, usize)>
+    where
+        F: Fn(&NodeId) -> T,
+        T: Ord + Copy + Default,
+    {
+        let value = track!(self.consensus_value(&f))?;
+        let support = match self.state {
+            ClusterState::Stable => self.new.iter().filter(|n| f(n) >= value).count(),
+            ClusterState::CatchUp => self.old.iter().filter(|n| f(n) >= value).count(),
+            ClusterState::Joint => self.new.union(&self.old).filter(|n| f(n) >= value).count(),
+        };
+        Ok((value, support))
+    }
+
+    /// 各メンバの複製済みログの終端から、現在の構成においてコミット可能なログの終端を返す.
+    ///
+    /// `match_index`は、各メンバのローカルログに複製済みの領域の終端を返す関数.
+    ///
+    /// 返り値は、安定状態では「全メンバの過半数が複製済みの地点」、
+    /// `CatchUp`状態では「旧メンバの過半数が複製済みの地点」、
+    /// `Joint`状態では「新旧それぞれの過半数が複製済みの地点の小さい方」となる.
+    ///
+    /// # Errors
+    ///
+    /// 未初期化の構成に対して呼び出された場合には、
+    /// `ErrorKind::InvalidInput`を理由としたエラーが返される.
+    pub fn committable_index<F>(&self, match_index: F) -> Result<LogIndex>
+    where
+        F: Fn(&NodeId) -> LogIndex,
+    {
+        track!(self.consensus_value(match_index))
+    }
+
+    /// 基本的には`consensus_value`メソッドと同様.
+    ///
+    /// ただし構成変更中には、常に新旧メンバ群の両方から、
+    /// 過半数の承認を要求するところが異なる.
+    pub(crate) fn full_consensus_value<F, T>(&self, f: F) -> Result<T>
+    where
+        F: Fn(&NodeId) -> T,
+        T: Ord + Copy + Default,
+    {
+        track!(self.ensure_bootstrapped())?;
+        if self.state.is_stable() {
+            Ok(median(&self.new, &f))
+        } else {
+            // joint consensus
+            Ok(cmp::min(median(&self.new, &f), median(&self.old, &f)))
+        }
+    }
+
+    fn ensure_bootstrapped(&self) -> Result<()> {
+        track_assert!(
+            self.is_bootstrapped(),
+            ErrorKind::InvalidInput,
+            "Uninitialized cluster config"
+        );
+        Ok(())
+    }
+}
+
+/// `ClusterConfig::observe`が返す、メンバ構成の読み取り専用のスナップショット.
+///
+/// 不変なので、`Arc`に包んで複数のスレッドから共有することができる.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MembershipView {
+    voters: ClusterMembers,
+    learners: ClusterMembers,
+    state: ClusterState,
+}
+impl MembershipView {
+    /// 投票権を持つメンバ群を返す.
+    pub fn voters(&self) -> &ClusterMembers {
+        &self.voters
+    }
+
+    /// 投票権を持たない学習者群を返す.
+    pub fn learners(&self) -> &ClusterMembers {
+        &self.learners
+    }
+
+    /// スナップショット取得時のクラスタ状態を返す.
+    pub fn state(&self) -> ClusterState {
+        self.state
+    }
+}
+
+fn encode_indices(
+    bytes: &mut Vec<u8>,
+    members: &ClusterMembers,
+    dictionary: &[NodeId],
+) -> Result<()> {
+    for member in members {
+        let index = track_assert_some!(
+            dictionary.iter().position(|n| n == member),
+            ErrorKind::InvalidInput,
+            "Unknown member: {:?}",
+            member
+        );
+        track_assert!(
+            index <= usize::from(u8::MAX),
+            ErrorKind::InvalidInput,
+            "Too large dictionary index: {}",
+            index
+        );
+        bytes.push(index as u8);
+    }
+    Ok(())
+}
+
+fn decode_indices<'a>(
+    bytes: &'a [u8],
+    len: usize,
+    dictionary: &[NodeId],
+) -> Result<(ClusterMembers, &'a [u8])> {
+    track_assert!(
+        len <= bytes.len(),
+        ErrorKind::InvalidInput,
+        "Truncated input"
+    );
+    let mut members = ClusterMembers::new();
+    for &index in &bytes[..len] {
+        let member = track_assert_some!(
+            dictionary.get(usize::from(index)),
+            ErrorKind::InvalidInput,
+            "Out of dictionary index: {}",
+            index
+        );
+        members.insert(member.clone());
+    }
+    Ok((members, &bytes[len..]))
+}
+
+fn median<F, T>(members: &ClusterMembers, f: F) -> T
+where
+    F: Fn(&NodeId) -> T,
     T: Ord + Copy + Default,
 {
     let mut values = members.iter().map(|n| f(n)).collect::<Vec<_>>();
@@ -207,3 +1418,1100 @@ where
         values[members.len() / 2]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    use crate::election::Term;
+    use crate::log::LogPosition;
+
+    fn members(ids: &[&str]) -> ClusterMembers {
+        ids.iter().map(|id| NodeId::new(*id)).collect()
+    }
+
+    fn match_indices(pairs: &[(&str, u64)]) -> HashMap<NodeId, LogIndex> {
+        pairs
+            .iter()
+            .map(|&(id, index)| (NodeId::new(id), LogIndex::new(index)))
+            .collect()
+    }
+
+    #[test]
+    fn same_effective_membership_works() {
+        let stable = ClusterConfig::new(members(&["a", "b", "c"]));
+        let catch_up = ClusterConfig::with_state(
+            members(&["a", "b", "c"]),
+            members(&["a", "b"]),
+            ClusterState::CatchUp,
+        );
+        assert_ne!(stable, catch_up);
+        assert!(stable.same_effective_membership(&catch_up));
+        assert!(catch_up.same_effective_membership(&stable));
+
+        let other = ClusterConfig::new(members(&["a", "b", "d"]));
+        assert!(!stable.same_effective_membership(&other));
+        assert!(!catch_up.same_effective_membership(&other));
+    }
+
+    #[test]
+    fn voter_ids_works() {
+        let new = members(&["c", "b", "d"]);
+        let old = members(&["a", "b"]);
+        for &state in &[
+            ClusterState::Stable,
+            ClusterState::CatchUp,
+            ClusterState::Joint,
+        ] {
+            let config = ClusterConfig::with_state(new.clone(), old.clone(), state);
+            let voters = config.voter_ids();
+            assert!(voters.windows(2).all(|w| w[0] < w[1]));
+            assert!(voters.iter().eq(config.primary_members().iter()));
+        }
+    }
+
+    #[test]
+    fn from_members_iter_works() {
+        let ids = vec![NodeId::new("b"), NodeId::new("a"), NodeId::new("b")];
+        let config = ClusterConfig::from_members_iter(ids).unwrap();
+        assert_eq!(config, ClusterConfig::new(members(&["a", "b"])));
+
+        let config = ClusterConfig::from_members_iter(vec![NodeId::new("a")]).unwrap();
+        assert_eq!(config, ClusterConfig::new(members(&["a"])));
+
+        let e = ClusterConfig::from_members_iter(Vec::new()).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn compact_encoding_works() {
+        let dictionary = ["a", "b", "c", "d", "e"]
+            .iter()
+            .map(|id| NodeId::new(*id))
+            .collect::<Vec<_>>();
+        let joint = ClusterConfig::with_state(
+            members(&["a", "b", "c", "e"]),
+            members(&["a", "b", "c", "d"]),
+            ClusterState::Joint,
+        );
+        let bytes = joint.encode_compact(&dictionary).unwrap();
+        assert_eq!(bytes.len(), 2 + 4 + 4);
+        assert_eq!(
+            ClusterConfig::decode_compact(&bytes, &dictionary).unwrap(),
+            joint
+        );
+
+        let stable = ClusterConfig::new(members(&["b", "d"]));
+        let bytes = stable.encode_compact(&dictionary).unwrap();
+        assert_eq!(bytes, [2, 1, 3, 0]);
+        assert_eq!(
+            ClusterConfig::decode_compact(&bytes, &dictionary).unwrap(),
+            stable
+        );
+
+        // 辞書に含まれないメンバは符号化できない
+        let e = joint.encode_compact(&dictionary[..4]).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+
+        for bytes in &[
+            &[][..],
+            &[3 << 6],
+            &[2, 1],
+            &[1, 0, 0, 9],
+            &[1, 7, 0],
+            &[0, 0, 0],
+            // 安定状態で旧メンバを含む
+            &[1, 0, 1, 1],
+        ] {
+            let e = ClusterConfig::decode_compact(bytes, &dictionary).unwrap_err();
+            assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn compact_encoding_rejects_too_many_old_members() {
+        let dictionary = (0..256)
+            .map(|i| NodeId::new(format!("node{}", i)))
+            .collect::<Vec<_>>();
+        let new = dictionary[..3].iter().cloned().collect::<ClusterMembers>();
+
+        // 旧メンバ数は一バイトで表現されるので、256個以上は符号化できない
+        let old = dictionary.iter().cloned().collect::<ClusterMembers>();
+        let joint = ClusterConfig::with_state(new.clone(), old, ClusterState::Joint);
+        let e = joint.encode_compact(&dictionary).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+
+        let old = dictionary[..255]
+            .iter()
+            .cloned()
+            .collect::<ClusterMembers>();
+        let joint = ClusterConfig::with_state(new, old, ClusterState::Joint);
+        let bytes = joint.encode_compact(&dictionary).unwrap();
+        assert_eq!(bytes.len(), 2 + 3 + 255);
+        assert_eq!(
+            ClusterConfig::decode_compact(&bytes, &dictionary).unwrap(),
+            joint
+        );
+    }
+
+    #[test]
+    fn fault_tolerance_works() {
+        let three = members(&["a", "b", "c"]);
+        let five = members(&["a", "b", "c", "d", "e"]);
+        assert_eq!(ClusterConfig::new(three.clone()).fault_tolerance(), 1);
+        assert_eq!(ClusterConfig::new(five.clone()).fault_tolerance(), 2);
+
+        let catch_up =
+            ClusterConfig::with_state(five.clone(), three.clone(), ClusterState::CatchUp);
+        assert_eq!(catch_up.fault_tolerance(), 1);
+
+        let joint = ClusterConfig::with_state(five.clone(), three.clone(), ClusterState::Joint);
+        assert_eq!(joint.fault_tolerance(), 1);
+
+        let joint = ClusterConfig::with_state(five, members(&["a"]), ClusterState::Joint);
+        assert_eq!(joint.fault_tolerance(), 0);
+    }
+
+    #[test]
+    fn acks_required_works() {
+        let three = members(&["a", "b", "c"]);
+        let four = members(&["a", "b", "c", "d"]);
+        let five = members(&["a", "b", "c", "d", "e"]);
+        assert_eq!(
+            ClusterConfig::new(members(&["a"])).acks_required(),
+            Quorum::Single(1)
+        );
+        assert_eq!(
+            ClusterConfig::new(three.clone()).acks_required(),
+            Quorum::Single(2)
+        );
+        assert_eq!(ClusterConfig::new(four).acks_required(), Quorum::Single(3));
+        assert_eq!(
+            ClusterConfig::new(five.clone()).acks_required(),
+            Quorum::Single(3)
+        );
+
+        // `CatchUp`状態では、旧構成の過半数のみが必要
+        let catch_up =
+            ClusterConfig::with_state(five.clone(), three.clone(), ClusterState::CatchUp);
+        assert_eq!(catch_up.acks_required(), Quorum::Single(2));
+
+        let joint = ClusterConfig::with_state(five, three, ClusterState::Joint);
+        assert_eq!(joint.acks_required(), Quorum::Joint { new: 3, old: 2 });
+    }
+
+    #[test]
+    fn is_change_in_flight_works() {
+        let stable = ClusterConfig::new(members(&["a", "b", "c"]));
+        assert!(!stable.is_change_in_flight());
+
+        let catch_up = stable.start_config_change(members(&["a", "b", "c", "d"]));
+        assert!(catch_up.is_change_in_flight());
+
+        let joint = catch_up.to_next_state();
+        assert!(joint.is_change_in_flight());
+        assert!(!joint.to_next_state().is_change_in_flight());
+    }
+
+    #[test]
+    fn should_defer_election_works() {
+        let config = ClusterConfig::new(members(&["a", "b", "c"]));
+        assert!(!config.should_defer_election(&"a".into()));
+
+        let priorities = vec![("a".into(), 1), ("b".into(), 10)]
+            .into_iter()
+            .collect();
+        let config = config.with_priorities(priorities);
+        assert_eq!(config.priority(&"c".into()), 0);
+
+        // 優先度の低いノードは立候補を遅らせ、最も優先度の高いノードのみが即座に立候補する
+        assert!(config.should_defer_election(&"a".into()));
+        assert!(config.should_defer_election(&"c".into()));
+        assert!(!config.should_defer_election(&"b".into()));
+
+        // 優先度は構成変更後も引き継がれるが、投票権を失ったノードは考慮されない
+        let config = config
+            .start_config_change(members(&["a", "c"]))
+            .to_next_state()
+            .to_next_state();
+        assert_eq!(config.priority(&"b".into()), 10);
+        assert!(!config.should_defer_election(&"a".into()));
+        assert!(config.should_defer_election(&"c".into()));
+    }
+
+    #[test]
+    fn other_voters_works() {
+        let stable = ClusterConfig::new(members(&["a", "b", "c"]));
+        assert_eq!(
+            stable.other_voters(&NodeId::new("a")),
+            [NodeId::new("b"), NodeId::new("c")]
+        );
+        assert_eq!(stable.other_voters(&NodeId::new("d")).len(), 3);
+    }
+
+    #[test]
+    fn is_leader_eligible_works() {
+        let config = ClusterConfig::new(members(&["a", "b", "c"]))
+            .start_config_change(members(&["a", "b", "c", "d"]));
+
+        assert!(config.is_leader_eligible(&"a".into(), |_| true));
+        assert!(!config.is_leader_eligible(&"a".into(), |_| false));
+
+        // 学習者は、ログの新しさに関わらず立候補できない
+        assert!(!config.is_voting_member(&"d".into()));
+        assert!(!config.is_leader_eligible(&"d".into(), |_| unreachable!()));
+
+        let joint = config.to_next_state();
+        assert!(joint.is_leader_eligible(&"d".into(), |_| true));
+        assert!(!joint.is_leader_eligible(&"e".into(), |_| true));
+    }
+
+    #[test]
+    fn needs_snapshot_works() {
+        let config = ClusterConfig::new(members(&["a", "b", "c"]))
+            .start_config_change(members(&["a", "b", "c", "d", "e"]));
+        let log_start = LogIndex::new(100);
+
+        // 新メンバ`d`は、スナップショットの位置よりも遅れている
+        assert!(config.needs_snapshot(&"d".into(), LogIndex::new(0), log_start));
+        assert!(config.needs_snapshot(&"d".into(), LogIndex::new(99), log_start));
+
+        // 新メンバ`e`は、スナップショット以降のエントリ群のみで追いつける
+        assert!(!config.needs_snapshot(&"e".into(), LogIndex::new(100), log_start));
+        assert!(!config.needs_snapshot(&"b".into(), LogIndex::new(120), log_start));
+
+        assert!(!config.needs_snapshot(&"f".into(), LogIndex::new(0), log_start));
+    }
+
+    #[test]
+    fn uninitialized_config_is_not_bootstrapped() {
+        let config = ClusterConfig::uninitialized();
+        assert!(!config.is_bootstrapped());
+        assert!(ClusterConfig::new(members(&["a"])).is_bootstrapped());
+        assert!(ClusterConfig::new(ClusterMembers::new()).is_bootstrapped());
+        assert_ne!(config, ClusterConfig::new(ClusterMembers::new()));
+
+        let e = config.consensus_value(|_| LogIndex::new(1)).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+        assert!(config.committable_index(|_| LogIndex::new(1)).is_err());
+        assert!(config.full_consensus_value(|_| LogIndex::new(1)).is_err());
+    }
+
+    #[test]
+    fn consensus_value_with_support_works() {
+        let values = match_indices(&[("a", 5), ("b", 3), ("c", 9), ("d", 1), ("e", 7)]);
+        let f = |n: &NodeId| values[n];
+
+        let stable = ClusterConfig::new(members(&["a", "b", "c"]));
+        assert_eq!(
+            stable.consensus_value_with_support(f).unwrap(),
+            (LogIndex::new(5), 2)
+        );
+
+        let stable = ClusterConfig::new(members(&["a", "b", "c", "d"]));
+        assert_eq!(
+            stable.consensus_value_with_support(f).unwrap(),
+            (LogIndex::new(3), 3)
+        );
+
+        // 新構成の過半数(`c`, `e`)と、旧構成の過半数(`a`, `c`)の両方が承認している
+        let joint = ClusterConfig::new(members(&["a", "b", "c"]))
+            .start_config_change(members(&["c", "d", "e"]))
+            .to_next_state();
+        assert_eq!(
+            joint.consensus_value_with_support(f).unwrap(),
+            (LogIndex::new(5), 3)
+        );
+    }
+
+    #[test]
+    fn change_removes_leader_works() {
+        let config = ClusterConfig::new(members(&["a", "b", "c"]));
+        let leader = NodeId::new("a");
+        assert!(config.change_removes_leader(&members(&["b", "c"]), &leader));
+        assert!(!config.change_removes_leader(&members(&["a", "b"]), &leader));
+        assert!(!config.change_removes_leader(&members(&["a", "b", "c", "d"]), &leader));
+    }
+
+    #[test]
+    fn change_id_works() {
+        let id = ChangeId::new(7);
+        let stable = ClusterConfig::new(members(&["a", "b", "c"]));
+        assert_eq!(stable.clone().with_change_id(id).change_id(), None);
+
+        let new = members(&["a", "b", "c", "d"]);
+        let catch_up = stable.start_config_change(new.clone()).with_change_id(id);
+        assert_eq!(catch_up.change_id(), Some(id));
+        assert!(catch_up.is_same_change(id, &new));
+        assert!(!catch_up.is_same_change(ChangeId::new(8), &new));
+        assert!(!catch_up.is_same_change(id, &members(&["a", "b"])));
+
+        let joint = catch_up.to_next_state();
+        assert!(joint.is_same_change(id, &new));
+        assert_eq!(joint.to_next_state().change_id(), None);
+    }
+
+    #[test]
+    fn quorum_overlap_works() {
+        let config = ClusterConfig::new(members(&["a", "b", "c", "d", "e"]));
+        let n = config.primary_members().len();
+        assert!(ClusterConfig::quorum_overlap(3, 3, n));
+        assert!(!ClusterConfig::quorum_overlap(2, 3, n));
+        assert!(ClusterConfig::quorum_overlap(1, 5, n));
+    }
+
+    #[test]
+    fn quorums_intersect_works() {
+        // 1 => 3: 旧構成の唯一のメンバは、全ての合意に参加する
+        let joint = ClusterConfig::with_state(
+            members(&["a", "b", "c"]),
+            members(&["a"]),
+            ClusterState::Joint,
+        );
+        assert!(joint.quorums_intersect());
+
+        // 新旧が互いに素な単一ノード同士でも、`Joint`状態では両方の合意が必要なので安全.
+        // ただし、どちらか一方が故障すると、構成変更が完了するまで合意が取れなくなる.
+        let joint =
+            ClusterConfig::with_state(members(&["b"]), members(&["a"]), ClusterState::Joint);
+        assert!(joint.quorums_intersect());
+        assert_eq!(joint.fault_tolerance(), 0);
+
+        let stable = ClusterConfig::new(members(&["a", "b", "c", "d"]));
+        assert!(stable.quorums_intersect());
+    }
+
+    #[test]
+    fn promote_learners_works() {
+        let stable = ClusterConfig::new(members(&["a", "b", "c"]));
+        assert!(stable.learners().is_empty());
+
+        let catch_up = stable.start_config_change(members(&["a", "b", "c", "d", "e", "f"]));
+        assert_eq!(catch_up.learners(), members(&["d", "e", "f"]));
+
+        let promoted = catch_up.promote_learners(&members(&["d", "e"])).unwrap();
+        assert_eq!(promoted.state(), ClusterState::Joint);
+        assert_eq!(promoted.new_members(), &members(&["a", "b", "c", "d", "e"]));
+        assert_eq!(promoted.old_members(), &members(&["a", "b", "c"]));
+        assert!(promoted.learners().is_empty());
+
+        let e = catch_up
+            .promote_learners(&members(&["a", "d"]))
+            .unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+        assert!(stable.promote_learners(&members(&["d"])).is_err());
+
+        // `Joint`状態では、進行中の構成変更を置き換えてしまうので、昇格できない
+        let joint = with_learner_x()
+            .start_config_change(members(&["a", "b", "c", "d"]))
+            .to_next_state();
+        assert_eq!(joint.state(), ClusterState::Joint);
+        assert!(joint.learners().contains(&NodeId::new("x")));
+        let e = joint.promote_learners(&members(&["x"])).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+    }
+
+    /// 降格済みの学習者`x`を持つ、安定状態の構成を返す.
+    fn with_learner_x() -> ClusterConfig {
+        let config = ClusterConfig::new(members(&["a", "b", "c", "x"]))
+            .demote_to_learner(NodeId::new("x"))
+            .unwrap()
+            .to_next_state()
+            .to_next_state();
+        assert!(config.state().is_stable());
+        config
+    }
+
+    #[test]
+    fn roll_works() {
+        let catch_up = ClusterConfig::new(members(&["a", "b", "c"]))
+            .start_config_change(members(&["a", "b", "c", "d"]));
+        let rolled = catch_up.roll("d".into(), "a".into()).unwrap();
+        assert_eq!(rolled.state(), ClusterState::Joint);
+        assert_eq!(rolled.old_members(), &members(&["a", "b", "c"]));
+        assert_eq!(rolled.new_members(), &members(&["b", "c", "d"]));
+
+        let stable = rolled.to_next_state();
+        assert_eq!(stable.primary_members(), &members(&["b", "c", "d"]));
+
+        let e = catch_up.roll("b".into(), "a".into()).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+        let e = catch_up.roll("d".into(), "e".into()).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+        let e = catch_up.roll("d".into(), "d".into()).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+
+        // 進行中の`Joint`状態の構成変更は、置き換えられない
+        let joint = with_learner_x()
+            .start_config_change(members(&["a", "b", "c", "d"]))
+            .to_next_state();
+        let e = joint.roll("x".into(), "a".into()).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+        let e = rolled.roll("d".into(), "b".into()).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn learner_ready_works() {
+        let values = match_indices(&[("a", 5), ("b", 3), ("c", 9), ("d", 9), ("e", 4)]);
+        let f = |n: &NodeId| values[n];
+
+        let catch_up = ClusterConfig::new(members(&["a", "b", "c"]))
+            .start_config_change(members(&["a", "b", "c", "d", "e"]));
+        let ready = catch_up.learner_ready(f, LogIndex::new(9));
+        assert_eq!(ready, [NodeId::new("d")]);
+
+        // 投票者は、追いついていても対象とならない
+        let stable = ClusterConfig::new(members(&["a", "b", "c"]));
+        assert!(stable.learner_ready(f, LogIndex::new(9)).is_empty());
+    }
+
+    #[test]
+    fn to_next_state_checked_works() {
+        let stable = ClusterConfig::new(members(&["a", "b", "c"]));
+        let e = stable.to_next_state_checked().unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+
+        let catch_up = stable.start_config_change(members(&["a", "b", "d"]));
+        let joint = catch_up.to_next_state_checked().unwrap();
+        assert_eq!(joint.state(), ClusterState::Joint);
+
+        let stable = joint.to_next_state_checked().unwrap();
+        assert_eq!(stable, ClusterConfig::new(members(&["a", "b", "d"])));
+        assert!(stable.to_next_state_checked().is_err());
+    }
+
+    #[test]
+    fn stage_to_works() {
+        let stable = ClusterConfig::new(members(&["a", "b", "c"]));
+        let stages = stable.stage_to(members(&["d", "e", "f"])).unwrap();
+        assert_eq!(
+            stages,
+            [
+                ClusterConfig::with_state(
+                    members(&["d", "e", "f"]),
+                    members(&["a", "b", "c"]),
+                    ClusterState::CatchUp
+                ),
+                ClusterConfig::with_state(
+                    members(&["d", "e", "f"]),
+                    members(&["a", "b", "c"]),
+                    ClusterState::Joint
+                ),
+                ClusterConfig::new(members(&["d", "e", "f"])),
+            ]
+        );
+
+        let e = stages[0].stage_to(members(&["a"])).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::Busy);
+        let e = stable.stage_to(members(&[])).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn new_members_caught_up_works() {
+        let stable = ClusterConfig::new(members(&["a", "b", "c"]));
+        let catch_up = stable.start_config_change(members(&["a", "b", "c", "d", "e"]));
+
+        let lagging = match_indices(&[("d", 10), ("e", 7)]);
+        assert!(!catch_up.new_members_caught_up(|n| lagging[n], LogIndex::new(10)));
+
+        let current = match_indices(&[("d", 10), ("e", 10)]);
+        assert!(catch_up.new_members_caught_up(|n| current[n], LogIndex::new(10)));
+        assert!(stable.new_members_caught_up(|_| LogIndex::new(0), LogIndex::new(10)));
+    }
+
+    #[test]
+    fn single_node_change_works() {
+        let stable = ClusterConfig::new(members(&["a", "b", "c"]));
+        assert!(!stable.is_single_node_change());
+
+        let add = stable
+            .start_config_change_checked(members(&["a", "b", "c", "d"]), true)
+            .unwrap();
+        assert!(add.is_single_node_change());
+        assert!(add.to_next_state().is_single_node_change());
+
+        let swap = members(&["a", "b", "d"]);
+        let e = stable
+            .start_config_change_checked(swap.clone(), true)
+            .unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+        let swapped = stable.start_config_change_checked(swap, false).unwrap();
+        assert!(!swapped.is_single_node_change());
+    }
+
+    #[test]
+    fn disjoint_change_is_rejected_under_strict_option() {
+        let stable = ClusterConfig::new(members(&["a", "b", "c"]));
+        assert!(stable.is_disjoint_change(&members(&["d", "e", "f"])));
+        assert!(!stable.is_disjoint_change(&members(&["c", "d", "e"])));
+
+        let e = stable
+            .start_config_change_strict(members(&["d", "e", "f"]))
+            .unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+        assert!(e.to_string().contains("Disjoint"), "{}", e);
+        assert!(stable
+            .start_config_change_checked(members(&["d", "e", "f"]), false)
+            .is_ok());
+
+        let overlapping = stable
+            .start_config_change_strict(members(&["c", "d", "e"]))
+            .unwrap();
+        assert_eq!(overlapping.new_members(), &members(&["c", "d", "e"]));
+        assert_eq!(overlapping.old_members(), &members(&["a", "b", "c"]));
+    }
+
+    #[test]
+    fn enter_joint_works() {
+        let stable = ClusterConfig::new(members(&["a", "b", "c"]));
+        let catch_up = stable.start_config_change(members(&["a", "b", "d"]));
+
+        let e = catch_up.enter_joint(false).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::Busy);
+
+        let joint = catch_up.enter_joint(true).unwrap();
+        assert_eq!(joint.state(), ClusterState::Joint);
+
+        for config in &[stable, joint] {
+            let e = config.enter_joint(true).unwrap_err();
+            assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn config_change_with_large_membership_works() {
+        let ids = (0..200)
+            .map(|i| format!("node{:03}", i))
+            .collect::<Vec<_>>();
+        let ids = ids.iter().map(|id| id.as_str()).collect::<Vec<_>>();
+        let stable = ClusterConfig::new(members(&ids[..100]));
+
+        // 100ノードの追加
+        let catch_up = stable.start_config_change(members(&ids));
+        assert_eq!(
+            catch_up,
+            ClusterConfig::with_state(members(&ids), members(&ids[..100]), ClusterState::CatchUp)
+        );
+        let joint = catch_up.to_next_state();
+        assert_eq!(
+            joint,
+            ClusterConfig::with_state(members(&ids), members(&ids[..100]), ClusterState::Joint)
+        );
+        let added = joint.to_next_state();
+        assert_eq!(added, ClusterConfig::new(members(&ids)));
+
+        // 150ノードの削除
+        let removed = added
+            .start_config_change(members(&ids[150..]))
+            .to_next_state()
+            .to_next_state();
+        assert_eq!(removed, ClusterConfig::new(members(&ids[150..])));
+        assert!(removed.old_members().is_empty());
+    }
+
+    #[test]
+    fn audit_works() {
+        let stable = ClusterConfig::new(members(&["a", "b", "c"]));
+        assert!(stable.audit().is_empty());
+        let catch_up = stable.start_config_change(members(&["a", "b"]));
+        assert!(catch_up.audit().is_empty());
+        assert!(catch_up.to_next_state().audit().is_empty());
+
+        let malformed =
+            ClusterConfig::with_state(members(&["a"]), members(&["b"]), ClusterState::Stable);
+        assert_eq!(malformed.audit(), [ConfigViolation::StableWithOldMembers]);
+
+        let malformed = ClusterConfig::with_state(members(&[]), members(&[]), ClusterState::Joint);
+        assert_eq!(
+            malformed.audit(),
+            [
+                ConfigViolation::EmptyNewMembers,
+                ConfigViolation::ChangeWithoutOldMembers
+            ]
+        );
+    }
+
+    #[test]
+    fn target_members_works() {
+        let stable = ClusterConfig::new(members(&["a", "b", "c"]));
+        assert_eq!(stable.target_members(), &members(&["a", "b", "c"]));
+
+        let catch_up = stable.start_config_change(members(&["a", "b"]));
+        for config in &[catch_up.clone(), catch_up.to_next_state()] {
+            assert_eq!(config.target_members(), &members(&["a", "b"]));
+            assert!(config.members().any(|n| n.as_str() == "c"));
+        }
+    }
+
+    #[test]
+    fn is_removable_works() {
+        let config = ClusterConfig::new(members(&["a", "b", "c"]));
+        assert!(config.is_removable(&NodeId::new("a")));
+        assert!(!config.is_removable(&NodeId::new("d")));
+
+        let single = ClusterConfig::new(members(&["a"]));
+        assert!(!single.is_removable(&NodeId::new("a")));
+    }
+
+    #[test]
+    fn should_shutdown_works() {
+        let joint = ClusterConfig::new(members(&["a", "b", "c"]))
+            .start_config_change(members(&["a", "b"]))
+            .to_next_state();
+        assert!(!joint.should_shutdown(&NodeId::new("c")));
+
+        let stable = joint.to_next_state();
+        assert!(stable.should_shutdown(&NodeId::new("c")));
+        assert!(!stable.should_shutdown(&NodeId::new("a")));
+        assert!(!stable.should_shutdown(&NodeId::new("b")));
+    }
+
+    #[test]
+    fn routable_members_lists_new_members_first() {
+        let joint = ClusterConfig::new(members(&["a", "b", "c"]))
+            .start_config_change(members(&["c", "d"]))
+            .to_next_state();
+        assert!(joint.state().is_joint());
+        let routable: Vec<_> = joint
+            .routable_members()
+            .iter()
+            .map(|n| n.as_str().to_owned())
+            .collect();
+        assert_eq!(routable, ["c", "d", "a", "b"]);
+    }
+
+    #[test]
+    fn configs_overlap_works() {
+        let a = ClusterConfig::new(members(&["a", "b", "c"]));
+        let b = ClusterConfig::new(members(&["c", "d", "e"]));
+        assert!(ClusterConfig::configs_overlap(&a, &b));
+
+        // `CatchUp`状態では、新構成のみのメンバは投票しない
+        let catch_up = ClusterConfig::new(members(&["x"])).start_config_change(members(&["a"]));
+        assert!(!ClusterConfig::configs_overlap(&a, &catch_up));
+        assert!(ClusterConfig::configs_overlap(
+            &a,
+            &catch_up.to_next_state()
+        ));
+
+        // 分断された二つの単一ノード構成
+        let x = ClusterConfig::new(members(&["x"]));
+        let y = ClusterConfig::new(members(&["y"]));
+        assert!(!ClusterConfig::configs_overlap(&x, &y));
+    }
+
+    #[test]
+    fn weights_work() {
+        let stable = ClusterConfig::new(members(&["a", "b", "c"]));
+        assert_eq!(stable.total_weight(), 3);
+        assert_eq!(stable.side_weights(), (3, 0));
+
+        let catch_up = stable.start_config_change(members(&["c", "d"]));
+        assert_eq!(catch_up.total_weight(), 3);
+        assert_eq!(catch_up.side_weights(), (1, 3));
+
+        let joint = catch_up.to_next_state();
+        assert_eq!(joint.total_weight(), 4);
+        assert_eq!(joint.side_weights(), (2, 3));
+    }
+
+    #[test]
+    fn change_index_works() {
+        let stable = ClusterConfig::new(members(&["a", "b"]));
+        assert_eq!(
+            stable
+                .clone()
+                .with_change_index(LogIndex::new(3))
+                .change_index(),
+            None
+        );
+
+        let catch_up = stable
+            .start_config_change(members(&["b", "c"]))
+            .with_change_index(LogIndex::new(10));
+        let joint = catch_up.to_next_state();
+        for config in &[catch_up, joint.clone()] {
+            assert_eq!(config.change_index(), Some(LogIndex::new(10)));
+            assert_eq!(
+                config.member_added_at(&NodeId::new("c")),
+                Some(LogIndex::new(10))
+            );
+            assert_eq!(
+                config.member_removed_at(&NodeId::new("a")),
+                Some(LogIndex::new(10))
+            );
+            assert_eq!(config.member_added_at(&NodeId::new("b")), None);
+            assert_eq!(config.member_removed_at(&NodeId::new("b")), None);
+        }
+
+        let stable = joint.to_next_state();
+        assert_eq!(stable.change_index(), None);
+        assert_eq!(stable.member_added_at(&NodeId::new("c")), None);
+    }
+
+    #[test]
+    fn tombstones_work() {
+        let untracked = ClusterConfig::new(members(&["a", "b", "c"]))
+            .start_config_change(members(&["a", "b"]))
+            .to_next_state()
+            .to_next_state();
+        assert!(!untracked.is_tombstoned(&NodeId::new("c")));
+        assert_eq!(untracked.tombstones(), None);
+
+        let stable = ClusterConfig::new(members(&["a", "b", "c"]))
+            .with_tombstones(BTreeMap::new())
+            .start_config_change(members(&["a", "b"]))
+            .with_change_index(LogIndex::new(5))
+            .to_next_state()
+            .to_next_state();
+        assert!(stable.is_tombstoned(&NodeId::new("c")));
+        assert!(!stable.is_tombstoned(&NodeId::new("a")));
+        assert_eq!(
+            stable.tombstones().and_then(|t| t.get(&NodeId::new("c"))),
+            Some(&LogIndex::new(5))
+        );
+
+        // 再び追加されたノードは、墓標から取り除かれる
+        let readded = stable.start_config_change(members(&["a", "b", "c"]));
+        assert!(!readded.is_tombstoned(&NodeId::new("c")));
+
+        let pruned = stable.clone().prune_tombstones(LogIndex::new(5));
+        assert!(pruned.is_tombstoned(&NodeId::new("c")));
+        let pruned = stable.prune_tombstones(LogIndex::new(6));
+        assert!(!pruned.is_tombstoned(&NodeId::new("c")));
+        assert_eq!(pruned.tombstones().map(|t| t.len()), Some(0));
+    }
+
+    #[test]
+    fn demote_to_learner_works() {
+        let stable = ClusterConfig::new(members(&["a", "b", "c", "d", "e"]));
+        let indices = match_indices(&[("a", 1), ("b", 1), ("c", 10), ("d", 10), ("e", 10)]);
+        assert_eq!(
+            stable.committable_index(|n| indices[n]).unwrap(),
+            LogIndex::new(10)
+        );
+
+        // 構成変更が完了するまでは、`e`は投票権を持ち続ける
+        let catch_up = stable.demote_to_learner(NodeId::new("e")).unwrap();
+        assert_eq!(catch_up.state(), ClusterState::CatchUp);
+        assert_eq!(catch_up.new_members(), &members(&["a", "b", "c", "d"]));
+        assert!(catch_up.learners().is_empty());
+        assert!(catch_up.is_voting_member(&NodeId::new("e")));
+
+        let e = catch_up.demote_to_learner(NodeId::new("d")).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+
+        let joint = catch_up.to_next_state();
+        assert!(joint.learners().is_empty());
+        assert_eq!(
+            joint.committable_index(|n| indices[n]).unwrap(),
+            LogIndex::new(1)
+        );
+
+        // 完了後も、`e`は学習者として構成に残る
+        let demoted = joint.to_next_state();
+        assert_eq!(demoted.state(), ClusterState::Stable);
+        assert_eq!(demoted.learners(), members(&["e"]));
+        assert!(!demoted.is_voting_member(&NodeId::new("e")));
+        assert!(demoted.is_known_node(&NodeId::new("e")));
+        assert!(!demoted.should_shutdown(&NodeId::new("e")));
+        assert_eq!(demoted.sorted_members().len(), 5);
+        assert_eq!(
+            demoted.committable_index(|n| indices[n]).unwrap(),
+            LogIndex::new(1)
+        );
+        assert_eq!(demoted.to_next_state().learners(), members(&["e"]));
+
+        let e = demoted.demote_to_learner(NodeId::new("e")).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+
+        // 新構成に含めることで、再び投票者に昇格する
+        let promoted = demoted
+            .start_config_change(members(&["a", "b", "c", "d", "e"]))
+            .to_next_state()
+            .to_next_state();
+        assert!(promoted.learners().is_empty());
+        assert!(promoted.is_voting_member(&NodeId::new("e")));
+
+        let single = ClusterConfig::new(members(&["a"]));
+        let e = single.demote_to_learner(NodeId::new("a")).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn observe_returns_immutable_snapshot() {
+        use std::sync::Arc;
+        use std::thread;
+
+        let config = ClusterConfig::new(members(&["a", "b", "c"]));
+        let config = config.start_config_change(members(&["a", "b", "c", "d"]));
+        let view = Arc::new(config.observe());
+        assert_eq!(view.state(), ClusterState::CatchUp);
+        assert_eq!(*view.voters(), members(&["a", "b", "c"]));
+        assert_eq!(*view.learners(), members(&["d"]));
+
+        // 以後の構成変更は、取得済みのスナップショットに影響しない
+        let config = config.to_next_state().to_next_state();
+        assert_eq!(config.observe().voters().len(), 4);
+
+        let shared = view.clone();
+        let handle = thread::spawn(move || shared.voters().len());
+        assert_eq!(handle.join().expect("Never fails"), 3);
+        assert_eq!(view.state(), ClusterState::CatchUp);
+        assert_eq!(*view.learners(), members(&["d"]));
+    }
+
+    #[test]
+    fn sizing_warning_works() {
+        let config = ClusterConfig::new(members(&["a", "b", "c"]));
+        assert_eq!(config.sizing_warning(), None);
+
+        let config = ClusterConfig::new(members(&["a", "b", "c", "d", "e"]));
+        assert_eq!(config.sizing_warning(), None);
+
+        let config = ClusterConfig::new(members(&["a", "b"]));
+        assert_eq!(
+            config.sizing_warning(),
+            Some(SizingWarning {
+                members: 2,
+                suggested: 3
+            })
+        );
+
+        let config = ClusterConfig::new(members(&["a", "b", "c", "d"]));
+        assert_eq!(
+            config.sizing_warning(),
+            Some(SizingWarning {
+                members: 4,
+                suggested: 5
+            })
+        );
+    }
+
+    #[test]
+    fn on_commit_advanced_works() {
+        let stable = ClusterConfig::new(members(&["a", "b", "c"]));
+        let mut config = stable.start_config_change(members(&["a", "b", "d"]));
+        let joint_entry_index = LogIndex::new(5);
+
+        // (コミット済みの位置, Jointエントリの位置, 追いついたかどうか, 期待される状態)
+        let steps = [
+            (1, None, false, ClusterState::CatchUp),
+            (2, None, false, ClusterState::CatchUp),
+            (3, None, true, ClusterState::Joint),
+            (3, Some(5), true, ClusterState::Joint),
+            (4, Some(5), true, ClusterState::Joint),
+            (5, Some(5), true, ClusterState::Stable),
+            (6, None, false, ClusterState::Stable),
+        ];
+        for &(committed, joint, caught_up, expected) in &steps {
+            let joint = joint.map(|_| joint_entry_index);
+            config = config.on_commit_advanced(LogIndex::new(committed), joint, caught_up);
+            assert_eq!(config.state(), expected, "committed={}", committed);
+        }
+        assert_eq!(config.primary_members(), &members(&["a", "b", "d"]));
+
+        // 一度の呼び出しでは、一段階のみ遷移する
+        let catch_up = stable.start_config_change(members(&["a", "b", "d"]));
+        let next = catch_up.on_commit_advanced(LogIndex::new(10), Some(LogIndex::new(1)), true);
+        assert_eq!(next.state(), ClusterState::Joint);
+
+        assert_eq!(
+            stable.on_commit_advanced(LogIndex::new(10), None, true),
+            stable
+        );
+    }
+
+    #[test]
+    fn sorted_members_uses_lexical_order() {
+        let config = ClusterConfig::new(members(&["node2", "node10", "node1"]));
+        assert_eq!(
+            config.sorted_members(),
+            vec![
+                NodeId::new("node1"),
+                NodeId::new("node10"),
+                NodeId::new("node2")
+            ]
+        );
+        assert_eq!(
+            config.sorted_members(),
+            config.members().cloned().collect::<Vec<_>>()
+        );
+
+        let config = ClusterConfig::with_state(
+            members(&["node3", "node20"]),
+            members(&["node1", "node3"]),
+            ClusterState::Joint,
+        );
+        assert_eq!(
+            config.sorted_members(),
+            vec![
+                NodeId::new("node1"),
+                NodeId::new("node20"),
+                NodeId::new("node3")
+            ]
+        );
+    }
+
+    #[test]
+    fn config_history_works() {
+        let term = Term::new(1);
+        let old = ClusterConfig::new(members(&["a", "b", "c"]));
+        let catch_up = old.start_config_change(members(&["b", "c", "d"]));
+        let joint = catch_up.to_next_state();
+        let stable = joint.to_next_state();
+        let suffix = LogSuffix {
+            head: LogPosition {
+                prev_term: term,
+                index: LogIndex::new(10),
+            },
+            entries: vec![
+                LogEntry::Noop { term },
+                LogEntry::Config {
+                    term,
+                    config: catch_up.clone(),
+                },
+                LogEntry::Command {
+                    term,
+                    command: vec![1],
+                },
+                LogEntry::Config {
+                    term,
+                    config: joint.clone(),
+                },
+                LogEntry::Config {
+                    term,
+                    config: stable.clone(),
+                },
+            ],
+        };
+        assert_eq!(
+            ClusterConfig::config_history(&suffix),
+            vec![
+                (LogIndex::new(11), catch_up),
+                (LogIndex::new(13), joint),
+                (LogIndex::new(14), stable)
+            ]
+        );
+        assert!(ClusterConfig::config_history(&LogSuffix::default()).is_empty());
+    }
+
+    #[test]
+    fn replay_works() {
+        let term = Term::new(1);
+        let config = |config: &ClusterConfig| LogEntry::Config {
+            term,
+            config: config.clone(),
+        };
+        let initial = ClusterConfig::new(members(&["a", "b", "c"]));
+
+        // 追加 => 削除
+        let add = initial.start_config_change(members(&["a", "b", "c", "d"]));
+        let added = add.to_next_state().to_next_state();
+        let remove = added.start_config_change(members(&["b", "c", "d"]));
+        let entries = vec![
+            config(&initial),
+            LogEntry::Noop { term },
+            config(&add),
+            config(&add.to_next_state()),
+            LogEntry::Command {
+                term,
+                command: vec![1],
+            },
+            config(&added),
+            config(&remove),
+            config(&remove.to_next_state()),
+            config(&remove.to_next_state().to_next_state()),
+        ];
+        let replayed = ClusterConfig::replay(&entries).unwrap();
+        assert_eq!(replayed.state(), ClusterState::Stable);
+        assert_eq!(*replayed.primary_members(), members(&["b", "c", "d"]));
+
+        // 途中で終わっている場合には、構成変更中の構成となる
+        let replayed = ClusterConfig::replay(&entries[..4]).unwrap();
+        assert_eq!(replayed.state(), ClusterState::Joint);
+
+        // 不正な遷移
+        let entries = vec![config(&initial), config(&add.to_next_state())];
+        assert_eq!(
+            ClusterConfig::replay(&entries).map_err(|e| *e.kind()),
+            Err(ErrorKind::InvalidInput)
+        );
+        assert!(ClusterConfig::replay(&[LogEntry::Noop { term }]).is_err());
+    }
+
+    #[test]
+    fn validate_against_log_works() {
+        let term = Term::new(1);
+        let old = ClusterConfig::new(members(&["a", "b", "c"]));
+        let catch_up = old.start_config_change(members(&["b", "c", "d"]));
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![
+                LogEntry::Config {
+                    term,
+                    config: catch_up.clone(),
+                },
+                LogEntry::Noop { term },
+            ],
+        };
+        assert!(catch_up.validate_against_log(&suffix).is_ok());
+        assert!(old.validate_against_log(&LogSuffix::default()).is_ok());
+
+        let e = old.validate_against_log(&suffix).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InconsistentState);
+        let message = e.to_string();
+        assert!(message.contains(&format!("{:?}", old)), "{}", message);
+        assert!(message.contains(&format!("{:?}", catch_up)), "{}", message);
+    }
+
+    #[test]
+    fn committable_index_works_in_stable_state() {
+        let config = ClusterConfig::new(members(&["a", "b", "c"]));
+        let indices = match_indices(&[("a", 10), ("b", 7), ("c", 3)]);
+        assert_eq!(
+            config.committable_index(|n| indices[n]).unwrap(),
+            LogIndex::new(7)
+        );
+
+        let config = ClusterConfig::new(members(&["a", "b", "c", "d"]));
+        let indices = match_indices(&[("a", 10), ("b", 7), ("c", 5), ("d", 3)]);
+        assert_eq!(
+            config.committable_index(|n| indices[n]).unwrap(),
+            LogIndex::new(5)
+        );
+    }
+
+    #[test]
+    fn committable_index_works_in_catch_up_state() {
+        let config = ClusterConfig::with_state(
+            members(&["c", "d", "e"]),
+            members(&["a", "b", "c"]),
+            ClusterState::CatchUp,
+        );
+        // 新メンバ(d, e)の進捗は考慮されない
+        let indices = match_indices(&[("a", 10), ("b", 8), ("c", 4), ("d", 0), ("e", 0)]);
+        assert_eq!(
+            config.committable_index(|n| indices[n]).unwrap(),
+            LogIndex::new(8)
+        );
+    }
+
+    #[test]
+    fn committable_index_works_in_joint_state() {
+        let config = ClusterConfig::with_state(
+            members(&["c", "d", "e"]),
+            members(&["a", "b", "c"]),
+            ClusterState::Joint,
+        );
+        let indices = match_indices(&[("a", 10), ("b", 8), ("c", 4), ("d", 6), ("e", 2)]);
+        // 旧メンバの過半数は8、新メンバの過半数は4
+        assert_eq!(
+            config.committable_index(|n| indices[n]).unwrap(),
+            LogIndex::new(4)
+        );
+    }
+}
@@ -0,0 +1,248 @@
+//! メッセージの符号化結果を圧縮するための仕組み.
+//!
+//! `Io`の実装者が、送信路上のメッセージのバイト列(e.g., 大きな`AppendEntriesCall`)を
+//! 圧縮・伸張するために利用する.
+use std::collections::HashMap;
+use trackable::error::ErrorKindExt;
+
+use crate::{ErrorKind, Result};
+
+/// バイト列の圧縮・伸張を行うためのトレイト.
+///
+/// `decompress(compress(x)) == x`が、任意の`x`に対して成り立つ必要がある.
+pub trait Compressor {
+    /// `bytes`を圧縮する.
+    fn compress(&self, bytes: &[u8]) -> Vec<u8>;
+
+    /// `compress`で圧縮されたバイト列を伸張する.
+    ///
+    /// # Errors
+    ///
+    /// `bytes`が不正な形式の場合には、`ErrorKind::InvalidInput`を理由としたエラーが返される.
+    fn decompress(&self, bytes: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// 圧縮を行わない`Compressor`実装.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopCompressor;
+impl Compressor for NoopCompressor {
+    fn compress(&self, bytes: &[u8]) -> Vec<u8> {
+        bytes.to_vec()
+    }
+
+    fn decompress(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        Ok(bytes.to_vec())
+    }
+}
+
+/// LZ77方式の簡易な`Compressor`実装.
+///
+/// 入力中の繰り返し部分を、過去の出現位置への参照に置き換える.
+/// 同じコマンドや`Term`が並ぶことの多いログエントリ群に対して有効.
+///
+/// 圧縮結果は、以下の二種類のトークンの列となる(数値は全てLEB128形式):
+///
+/// - リテラル: `0`, 長さ, バイト列
+/// - 参照: `1`, 距離, 長さ
+///
+/// 伸張結果の長さは`max_output_len`で制限され、それを超える入力は不正なものとして扱われる.
+#[derive(Debug, Clone, Copy)]
+pub struct LzCompressor {
+    max_output_len: usize,
+}
+impl LzCompressor {
+    /// 伸張結果の長さの上限のデフォルト値(64MiB).
+    pub const DEFAULT_MAX_OUTPUT_LEN: usize = 64 * 1024 * 1024;
+
+    const MIN_MATCH: usize = 4;
+    const MAX_DISTANCE: usize = 1 << 16;
+
+    /// 新しい`LzCompressor`インスタンスを生成する.
+    pub fn new() -> Self {
+        LzCompressor {
+            max_output_len: Self::DEFAULT_MAX_OUTPUT_LEN,
+        }
+    }
+
+    /// 伸張結果の長さの上限を設定する.
+    ///
+    /// 伸張結果が`max_output_len`を超える入力に対しては、`decompress`が`ErrorKind::InvalidInput`を返す.
+    pub fn with_max_output_len(mut self, max_output_len: usize) -> Self {
+        self.max_output_len = max_output_len;
+        self
+    }
+
+    /// 伸張結果の長さの上限を返す.
+    pub fn max_output_len(&self) -> usize {
+        self.max_output_len
+    }
+
+    fn check_output_len(&self, current: usize, additional: usize) -> Result<()> {
+        track_assert!(
+            additional <= self.max_output_len.saturating_sub(current),
+            ErrorKind::InvalidInput,
+            "Too large output: {} + {} > {}",
+            current,
+            additional,
+            self.max_output_len
+        );
+        Ok(())
+    }
+
+    fn flush_literals(out: &mut Vec<u8>, literals: &[u8]) {
+        if !literals.is_empty() {
+            out.push(0);
+            put_varint(out, literals.len() as u64);
+            out.extend_from_slice(literals);
+        }
+    }
+}
+impl Default for LzCompressor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Compressor for LzCompressor {
+    fn compress(&self, bytes: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut last_seen = HashMap::new();
+        let mut literal_start = 0;
+        let mut i = 0;
+        while i + Self::MIN_MATCH <= bytes.len() {
+            let key = &bytes[i..i + Self::MIN_MATCH];
+            let candidate = last_seen.insert(key, i);
+            let start = match candidate {
+                Some(start) if i - start <= Self::MAX_DISTANCE => start,
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            };
+            let len = bytes[i..]
+                .iter()
+                .zip(&bytes[start..])
+                .take_while(|(a, b)| a == b)
+                .count();
+            Self::flush_literals(&mut out, &bytes[literal_start..i]);
+            out.push(1);
+            put_varint(&mut out, (i - start) as u64);
+            put_varint(&mut out, len as u64);
+            i += len;
+            literal_start = i;
+        }
+        Self::flush_literals(&mut out, &bytes[literal_start..]);
+        out
+    }
+
+    fn decompress(&self, mut bytes: &[u8]) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        while let Some((&tag, rest)) = bytes.split_first() {
+            bytes = rest;
+            match tag {
+                0 => {
+                    let len = track!(take_varint(&mut bytes))? as usize;
+                    track_assert!(
+                        len <= bytes.len(),
+                        ErrorKind::InvalidInput,
+                        "Truncated literal"
+                    );
+                    track!(self.check_output_len(out.len(), len))?;
+                    out.extend_from_slice(&bytes[..len]);
+                    bytes = &bytes[len..];
+                }
+                1 => {
+                    let distance = track!(take_varint(&mut bytes))? as usize;
+                    let len = track!(take_varint(&mut bytes))? as usize;
+                    track_assert!(
+                        0 < distance && distance <= out.len(),
+                        ErrorKind::InvalidInput,
+                        "Invalid distance: {}",
+                        distance
+                    );
+                    track!(self.check_output_len(out.len(), len))?;
+                    // 参照先と参照元が重なる場合があるので、一バイトずつ複製する
+                    let start = out.len() - distance;
+                    for j in 0..len {
+                        let b = out[start + j];
+                        out.push(b);
+                    }
+                }
+                _ => track_panic!(ErrorKind::InvalidInput, "Unknown token: {}", tag),
+            }
+        }
+        Ok(out)
+    }
+}
+
+fn put_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn take_varint(bytes: &mut &[u8]) -> Result<u64> {
+    let mut n = 0;
+    for shift in (0..64).step_by(7) {
+        let (&b, rest) = bytes
+            .split_first()
+            .ok_or_else(|| ErrorKind::InvalidInput.cause("Truncated varint"))?;
+        *bytes = rest;
+        n |= u64::from(b & 0x7F) << shift;
+        if b < 0x80 {
+            return Ok(n);
+        }
+    }
+    track_panic!(ErrorKind::InvalidInput, "Too long varint")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lz_compressor_roundtrip_works() {
+        let inputs: Vec<Vec<u8>> = vec![
+            Vec::new(),
+            b"abc".to_vec(),
+            b"abcabcabcabcabcabc".to_vec(),
+            (0..1000).map(|i| (i % 7) as u8).collect(),
+            (0..1000).map(|i| (i * 31 % 251) as u8).collect(),
+        ];
+        for input in inputs {
+            let compressed = LzCompressor::new().compress(&input);
+            assert_eq!(LzCompressor::new().decompress(&compressed).unwrap(), input);
+        }
+
+        let repetitive = vec![42; 10_000];
+        assert!(LzCompressor::new().compress(&repetitive).len() < 100);
+    }
+
+    #[test]
+    fn lz_compressor_rejects_malformed_input() {
+        for input in &[&[2][..], &[0, 5, 1], &[1, 1, 3], &[0, 0x80]] {
+            let e = LzCompressor::new().decompress(input).unwrap_err();
+            assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+        }
+
+        // 巨大な長さの参照によって、際限なくメモリを消費することはない
+        let mut input = vec![0, 1, 7, 1, 1];
+        put_varint(&mut input, u64::MAX);
+        let e = LzCompressor::new().decompress(&input).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn lz_compressor_limits_output_len() {
+        let input = vec![42; 1000];
+        let compressed = LzCompressor::new().compress(&input);
+
+        let compressor = LzCompressor::new().with_max_output_len(1000);
+        assert_eq!(compressor.decompress(&compressed).unwrap(), input);
+
+        let compressor = LzCompressor::new().with_max_output_len(999);
+        let e = compressor.decompress(&compressed).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -1,8 +1,10 @@
-use futures::Future;
+use futures::{Async, Future, Poll};
+use std::time::Instant;
 
-use crate::election::{Ballot, Role};
+use crate::election::{Ballot, Role, Term};
 use crate::log::{Log, LogIndex, LogPrefix, LogSuffix};
 use crate::message::Message;
+use crate::node::NodeId;
 use crate::{Error, Result};
 
 /// Raftの実行に必要なI/O機能を提供するためのトレイト.
@@ -53,12 +55,41 @@ pub trait Io {
     /// 呼び出しで`Err`を返すこと.
     fn send_message(&mut self, message: Message);
 
+    /// メッセージの送信先となり得る、ローカルノード以外のノード群を返す.
+    ///
+    /// デフォルト実装は常に空のリストを返す.
+    fn peers(&self) -> Vec<NodeId> {
+        Vec::new()
+    }
+
+    /// `peers`の内、現在疎通が取れているノード群を返す.
+    ///
+    /// ヘルスチェックや、構成上のメンバ群と実際の接続状況を突き合わせるために利用される.
+    ///
+    /// 実装側で疎通状況を把握していない場合には`None`を返す.
+    /// これは「どのノードとも疎通が取れていない」ことを意味する`Some(vec![])`とは区別される.
+    /// デフォルト実装は常に`None`を返す.
+    fn connected_peers(&self) -> Option<Vec<NodeId>> {
+        None
+    }
+
     /// ローカルノードの投票状況を保存する.
     fn save_ballot(&mut self, ballot: Ballot) -> Self::SaveBallot;
 
     /// ローカルノードの前回の投票状況を取得する.
     fn load_ballot(&mut self) -> Self::LoadBallot;
 
+    /// 永続化済みの投票状況の`Term`を返す.
+    ///
+    /// ノードの起動時に、`load_ballot`の完了を待つことなく、
+    /// 現在の`Term`を把握するために利用される.
+    ///
+    /// 投票状況が保存されていない場合、あるいは実装側で即座に値を求めることができない場合には`None`を返す.
+    /// デフォルト実装は常に`None`を返す.
+    fn durable_ballot_term(&self) -> Option<Term> {
+        None
+    }
+
     /// ローカルログの前半部分(i.e., スナップショット)を保存する.
     ///
     /// 保存に成功した場合は、それ以前のログ領域は破棄してしまって構わない.
@@ -71,6 +102,25 @@ pub trait Io {
     /// (リーダの入れ替えにより、ログの未コミット部分で競合が発生したことを示している)
     fn save_log_suffix(&mut self, suffix: &LogSuffix) -> Self::SaveLog;
 
+    /// 投票状況とローカルログの末尾部分を、一度の永続化処理でまとめて保存する.
+    ///
+    /// `ballot`が`None`の場合には、`suffix`のみを保存する.
+    /// `suffix`の扱いは`save_log_suffix`と同様.
+    ///
+    /// 新しいリーダから最初に受け取ったエントリ群を追記する際に、フォロワーが利用する.
+    /// 実装側で両者を一度に永続化できる場合には、そのようにオーバーライドすることで、
+    /// fsyncの回数を半減でき、かつ一方のみが保存された状態でクラッシュすることも無くなる.
+    ///
+    /// デフォルト実装は、`save_ballot`と`save_log_suffix`を個別に呼び出す.
+    fn save_ballot_and_log(
+        &mut self,
+        ballot: Option<Ballot>,
+        suffix: &LogSuffix,
+    ) -> SaveBallotAndLog<Self::SaveBallot, Self::SaveLog> {
+        let ballot = ballot.map(|ballot| self.save_ballot(ballot));
+        SaveBallotAndLog::new(ballot, self.save_log_suffix(suffix))
+    }
+
     /// ローカルログの指定範囲のエントリを取得する.
     ///
     /// 範囲は`start`から始まり、`end`を含まない最後のエントリまでを取得する.
@@ -83,13 +133,786 @@ pub trait Io {
     /// ただし、`start`とは異なる位置から、エントリの取得を開始することは許可されない.
     fn load_log(&mut self, start: LogIndex, end: Option<LogIndex>) -> Self::LoadLog;
 
+    /// 永続化済みのローカルログの最後のエントリのインデックスと`Term`を返す.
+    ///
+    /// ノードの起動時に、`load_log`で読み込んだログが、
+    /// 永続化済みの末尾まで欠けることなく揃っているかを検証するために利用される.
+    ///
+    /// ログが空の場合、あるいは実装側で即座に値を求めることができない場合には`None`を返す.
+    /// デフォルト実装は常に`None`を返す.
+    fn last_log_position(&self) -> Option<(LogIndex, Term)> {
+        None
+    }
+
+    /// 状態機械に適用済みの最後のエントリのインデックスを保存する.
+    ///
+    /// コミット済みの位置とは異なり、適用済みの位置はログから導出できないので、
+    /// 再起動を跨いで各エントリを一度だけ適用するためには、状態機械と合わせて永続化する必要がある.
+    ///
+    /// デフォルト実装は何もしない.
+    fn save_applied_index(&mut self, index: LogIndex) {
+        let _ = index;
+    }
+
+    /// `save_applied_index`で保存された、適用済みの最後のエントリのインデックスを返す.
+    ///
+    /// デフォルト実装は常に`None`を返す.
+    fn load_applied_index(&self) -> Option<LogIndex> {
+        None
+    }
+
+    /// ローカルログの`index`の位置にあるエントリの`Term`を返す.
+    ///
+    /// `load_log`とは異なり、エントリ群をロードすることなく、単一のエントリの`Term`のみを取得したい場合に利用される.
+    /// `index`がスナップショットに含まれる場合には、スナップショットの終端の`Term`
+    /// (i.e., スナップショットに含まれる最後のエントリの`Term`)を返す.
+    ///
+    /// 該当するエントリが存在しない場合、あるいは実装側で即座に値を求めることができない場合には`None`を返す.
+    /// デフォルト実装は常に`None`を返す.
+    fn load_entry_term(&self, index: LogIndex) -> Result<Option<Term>> {
+        let _ = index;
+        Ok(None)
+    }
+
     /// 選挙における役割に応じた時間のタイムアウトオブジェクトを生成する.
     fn create_timeout(&mut self, role: Role) -> Self::Timeout;
 
+    /// 投票状況やログの保存処理に対するタイムアウトオブジェクトを生成する.
+    ///
+    /// 保存処理の開始時に呼び出され、完了前にタイムアウトに達した場合には、
+    /// `ErrorKind::Other`を理由としたエラーによりノードが停止する
+    /// (ストレージが応答しなくなっている可能性が高いので、利用者はその状態を調査すべきである).
+    ///
+    /// デフォルト実装は常に`None`を返す(i.e., 保存処理の完了を無期限に待つ).
+    fn create_persist_timeout(&mut self) -> Option<Self::Timeout> {
+        None
+    }
+
     /// I/O処理を行う余裕があるかどうかを返す.
     ///
     /// これが`true`を返している間は、フォロワーの同期処理は実施されない.
     fn is_busy(&mut self) -> bool {
         false
     }
+
+    /// ローカルログのコミット済み領域が進んだ場合に呼び出される.
+    ///
+    /// `committed_tail`は、新しいコミット済み領域の終端(i.e., 次にコミットされるエントリのインデックス).
+    ///
+    /// コミットされたエントリの内容を取得したい場合には、`Event::Committed`を利用すること.
+    /// デフォルト実装は何もしない.
+    fn on_commit(&mut self, committed_tail: LogIndex) {
+        let _ = committed_tail;
+    }
+
+    /// 現在時刻を返す.
+    ///
+    /// ノードが時刻を記録する場合(e.g., 構成変更の開始時刻)に、`Instant::now()`の代わりに使用される.
+    /// 実装側で、テスト用の時計などに差し替えることができる.
+    /// デフォルト実装は`Instant::now()`を返す.
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    /// ノードのポーリングのループが一周する度に呼び出される.
+    ///
+    /// 実装側で、定期的に実施したい処理(e.g., スナップショットの作成判定)を行うために利用できる.
+    /// 呼び出し間隔は一定ではないので、時間の計測には使用しないこと.
+    /// デフォルト実装は何もしない.
+    fn tick(&mut self) {}
+}
+
+/// `Io::save_ballot_and_log`が返す`Future`.
+///
+/// 投票状況の保存処理(存在する場合)と、ログの保存処理の両方が完了した時点で完了する.
+#[derive(Debug)]
+pub struct SaveBallotAndLog<B, L> {
+    ballot: Option<B>,
+    log: Option<L>,
+}
+impl<B, L> SaveBallotAndLog<B, L> {
+    /// 新しい`SaveBallotAndLog`インスタンスを生成する.
+    ///
+    /// 両者を一度に永続化する実装の場合には、`ballot`には`None`を指定して、
+    /// `log`にその保存処理の`Future`を指定すれば良い.
+    pub fn new(ballot: Option<B>, log: L) -> Self {
+        SaveBallotAndLog {
+            ballot,
+            log: Some(log),
+        }
+    }
+}
+impl<B, L> Future for SaveBallotAndLog<B, L>
+where
+    B: Future<Item = (), Error = Error>,
+    L: Future<Item = (), Error = Error>,
+{
+    type Item = ();
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Async::Ready(Some(())) = track!(self.ballot.poll())? {
+            self.ballot = None;
+        }
+        if let Async::Ready(Some(())) = track!(self.log.poll())? {
+            self.log = None;
+        }
+        if self.ballot.is_none() && self.log.is_none() {
+            Ok(Async::Ready(()))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "compression")]
+    use std::sync::Arc;
+    use trackable::result::TestResult;
+
+    use super::*;
+    use crate::cluster::ClusterConfig;
+    #[cfg(feature = "compression")]
+    use crate::compression::LzCompressor;
+    use crate::log::{LogEntry, LogPosition};
+    use crate::message::{AppendEntriesCall, MessageHeader, RequestVoteReply, SequenceNumber};
+    use crate::node::NodeId;
+    #[cfg(feature = "compression")]
+    use crate::test_util::tests::TestCompressor;
+    use crate::test_util::tests::{
+        decode_message, encode_message, TestClock, TestIoBuilder, TestNetwork, STORAGE_VERSION,
+    };
+    use crate::ErrorKind;
+
+    fn vote_reply(from: &str, to: &str, term: u64) -> Message {
+        RequestVoteReply {
+            header: MessageHeader {
+                sender: from.into(),
+                destination: to.into(),
+                seq_no: SequenceNumber::new(0),
+                term: term.into(),
+            },
+            voted: false,
+        }
+        .into()
+    }
+
+    #[test]
+    fn last_log_position_works() {
+        let mut io = TestIoBuilder::new().add_member("node1".into()).finish();
+        assert_eq!(io.last_log_position(), None);
+
+        let term = Term::new(2);
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![
+                LogEntry::Noop { term: Term::new(1) },
+                LogEntry::Noop { term },
+                LogEntry::Command {
+                    term,
+                    command: vec![1],
+                },
+            ],
+        };
+        io.save_log_suffix(&suffix);
+        assert_eq!(io.last_log_position(), Some((LogIndex::new(2), term)));
+    }
+
+    #[test]
+    fn load_entry_term_works() -> TestResult {
+        let config = ClusterConfig::new(vec!["a".into()].into_iter().collect());
+        let mut io = TestIoBuilder::new().add_member("a".into()).finish();
+        assert_eq!(track!(io.load_entry_term(LogIndex::new(0)))?, None);
+
+        let entries = [1, 1, 2, 2, 3]
+            .iter()
+            .map(|&t| LogEntry::Noop { term: Term::new(t) })
+            .collect();
+        io.save_log_suffix(&LogSuffix {
+            head: LogPosition::default(),
+            entries,
+        });
+        io.save_log_prefix(LogPrefix {
+            tail: LogPosition {
+                prev_term: Term::new(2),
+                index: LogIndex::new(3),
+            },
+            config,
+            snapshot: Vec::new(),
+        });
+
+        // スナップショットに含まれる位置
+        assert_eq!(
+            track!(io.load_entry_term(LogIndex::new(0)))?,
+            Some(Term::new(2))
+        );
+
+        // エントリとして保存されている位置
+        assert_eq!(
+            track!(io.load_entry_term(LogIndex::new(3)))?,
+            Some(Term::new(2))
+        );
+        assert_eq!(
+            track!(io.load_entry_term(LogIndex::new(4)))?,
+            Some(Term::new(3))
+        );
+        assert_eq!(track!(io.load_entry_term(LogIndex::new(5)))?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn verify_integrity_works() {
+        let config = ClusterConfig::new(vec!["a".into()].into_iter().collect());
+        let mut io = TestIoBuilder::new().add_member("a".into()).finish();
+        let entries = (0..5)
+            .map(|i| LogEntry::Command {
+                term: Term::new(1),
+                command: vec![i],
+            })
+            .collect();
+        io.save_log_suffix(&LogSuffix {
+            head: LogPosition::default(),
+            entries,
+        });
+        assert!(io.verify_integrity());
+
+        // 正規の手段による上書きや切り詰めは、破損とはみなされない
+        io.save_log_suffix(&LogSuffix {
+            head: LogPosition {
+                prev_term: Term::new(1),
+                index: LogIndex::new(3),
+            },
+            entries: vec![LogEntry::Noop { term: Term::new(2) }],
+        });
+        io.save_log_prefix(LogPrefix {
+            tail: LogPosition {
+                prev_term: Term::new(1),
+                index: LogIndex::new(2),
+            },
+            config,
+            snapshot: Vec::new(),
+        });
+        assert!(io.verify_integrity());
+
+        // 保存済みのエントリを直接書き換える
+        io.store.lock().unwrap().suffix.entries[0] = LogEntry::Command {
+            term: Term::new(1),
+            command: vec![100],
+        };
+        assert!(!io.verify_integrity());
+    }
+
+    #[test]
+    fn iter_entries_borrows_stored_entries() {
+        let mut io = TestIoBuilder::new().add_member("a".into()).finish();
+        let entries = (0..10_000)
+            .map(|i| LogEntry::Command {
+                term: Term::new(1),
+                command: vec![i as u8],
+            })
+            .collect();
+        io.save_log_suffix(&LogSuffix {
+            head: LogPosition::default(),
+            entries,
+        });
+
+        let store = io.store.lock().unwrap();
+        let mut count = 0;
+        for (i, (index, entry)) in store.iter_entries().enumerate() {
+            assert_eq!(index, LogIndex::new(i as u64));
+            // 複製ではなく、保存済みのエントリそのものを参照している
+            assert!(std::ptr::eq(entry, &store.suffix.entries[i]));
+            count += 1;
+        }
+        assert_eq!(count, 10_000);
+    }
+
+    #[test]
+    fn save_ballot_and_log_works() -> TestResult {
+        let mut io = TestIoBuilder::new().add_member("a".into()).finish();
+        let ballot = Ballot {
+            term: Term::new(3),
+            voted_for: "a".into(),
+        };
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![
+                LogEntry::Noop { term: Term::new(1) },
+                LogEntry::Noop { term: Term::new(3) },
+            ],
+        };
+        track!(io.save_ballot_and_log(Some(ballot.clone()), &suffix).wait())?;
+        assert_eq!(io.persist_count, 1);
+        assert_eq!(
+            io.ballots.lock().unwrap().last().map(|b| &b.value),
+            Some(&ballot)
+        );
+        assert_eq!(io.store.lock().unwrap().suffix, suffix);
+
+        // 投票状況を伴わない場合には、ログのみが保存される
+        let next = LogSuffix {
+            head: suffix.tail(),
+            entries: vec![LogEntry::Noop { term: Term::new(3) }],
+        };
+        track!(io.save_ballot_and_log(None, &next).wait())?;
+        assert_eq!(io.persist_count, 2);
+        assert_eq!(io.ballots.lock().unwrap().len(), 1);
+        assert_eq!(
+            io.last_log_position(),
+            Some((LogIndex::new(2), Term::new(3)))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn load_rejects_unsupported_storage_version() -> TestResult {
+        let mut io = TestIoBuilder::new().add_member("a".into()).finish();
+        let ballot = Ballot {
+            term: Term::new(1),
+            voted_for: "a".into(),
+        };
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![LogEntry::Noop { term: Term::new(1) }],
+        };
+        track!(io.save_ballot_and_log(Some(ballot.clone()), &suffix).wait())?;
+        assert_eq!(track!(io.load_ballot().wait())?, Some(ballot));
+
+        // 将来の形式で保存されたレコードは、誤って解釈されずにエラーとなる
+        io.storage_version = STORAGE_VERSION + 1;
+        let next = LogSuffix {
+            head: suffix.tail(),
+            entries: vec![LogEntry::Noop { term: Term::new(2) }],
+        };
+        let next_ballot = Ballot {
+            term: Term::new(2),
+            voted_for: "a".into(),
+        };
+        track!(io.save_ballot_and_log(Some(next_ballot), &next).wait())?;
+        let e = io.load_ballot().wait().unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::Other);
+        assert!(e
+            .to_string()
+            .contains("Unsupported storage version: 2 (supported: 1)"));
+
+        // バージョンはレコード毎に検査されるので、対応済みの形式のエントリのみであればロードできる
+        match track!(io.load_log(LogIndex::new(0), Some(LogIndex::new(1))).wait())? {
+            Log::Suffix(loaded) => assert_eq!(loaded.entries, suffix.entries[..1]),
+            Log::Prefix(_) => panic!("Unexpected prefix"),
+        }
+        let e = io.load_log(LogIndex::new(0), None).wait().unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::Other);
+        Ok(())
+    }
+
+    #[test]
+    fn durable_ballot_term_works() {
+        let mut io = TestIoBuilder::new().add_member("node1".into()).finish();
+        assert_eq!(io.durable_ballot_term(), None);
+
+        io.save_ballot(Ballot {
+            term: Term::new(7),
+            voted_for: "node1".into(),
+        });
+        assert_eq!(io.durable_ballot_term(), Some(Term::new(7)));
+    }
+
+    #[test]
+    fn auto_compaction_works() {
+        let config = ClusterConfig::new(vec!["a".into()].into_iter().collect());
+        let mut io = TestIoBuilder::new()
+            .add_member("a".into())
+            .auto_compaction(3, move |tail| LogPrefix {
+                tail,
+                config: config.clone(),
+                snapshot: vec![tail.index.as_u64() as u8],
+            })
+            .finish();
+        let entries = (0..5)
+            .map(|_| LogEntry::Noop { term: Term::new(1) })
+            .collect();
+        io.save_log_suffix(&LogSuffix {
+            head: LogPosition::default(),
+            entries,
+        });
+
+        // 閾値を超えていても、コミットされるまではスナップショットは作成されない
+        assert!(io.store.lock().unwrap().prefix.is_none());
+
+        io.on_commit(LogIndex::new(4));
+        let store = io.store.lock().unwrap();
+        let prefix = store.prefix.as_ref().expect("No snapshot");
+        assert_eq!(prefix.tail.index, LogIndex::new(4));
+        assert_eq!(prefix.snapshot, vec![4]);
+        assert_eq!(store.suffix.head.index, LogIndex::new(4));
+        assert_eq!(store.suffix.entries.len(), 1);
+    }
+
+    #[test]
+    fn auto_compaction_on_tick_works() {
+        let config = ClusterConfig::new(vec!["a".into()].into_iter().collect());
+        let mut io = TestIoBuilder::new()
+            .add_member("a".into())
+            .auto_compaction_on_tick(3, move |tail| LogPrefix {
+                tail,
+                config: config.clone(),
+                snapshot: Vec::new(),
+            })
+            .finish();
+        let entries = (0..5)
+            .map(|_| LogEntry::Noop { term: Term::new(1) })
+            .collect();
+        io.save_log_suffix(&LogSuffix {
+            head: LogPosition::default(),
+            entries,
+        });
+
+        // コミット時には判定されない
+        io.on_commit(LogIndex::new(4));
+        assert!(io.store.lock().unwrap().prefix.is_none());
+
+        io.tick();
+        assert_eq!(io.ticks, 1);
+        let store = io.store.lock().unwrap();
+        let prefix = store.prefix.as_ref().expect("No snapshot");
+        assert_eq!(prefix.tail.index, LogIndex::new(4));
+        assert_eq!(store.suffix.entries.len(), 1);
+    }
+
+    #[test]
+    fn save_log_suffix_rejects_hole() {
+        let mut io = TestIoBuilder::new().add_member("node1".into()).finish();
+        let term = Term::new(1);
+        let entries = (0..4).map(|_| LogEntry::Noop { term }).collect();
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries,
+        };
+        assert!(io.save_log_suffix(&suffix).wait().is_ok());
+        assert_eq!(io.last_log_position(), Some((LogIndex::new(3), term)));
+
+        let suffix = LogSuffix {
+            head: LogPosition {
+                prev_term: term,
+                index: LogIndex::new(6),
+            },
+            entries: vec![LogEntry::Noop { term }],
+        };
+        let e = io.save_log_suffix(&suffix).wait().unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InconsistentState);
+
+        // 拒否された場合もローカルログは変わらないので、リーダはこの位置から再送すればよい
+        assert_eq!(io.last_log_position(), Some((LogIndex::new(3), term)));
+    }
+
+    #[test]
+    fn reset_log_to_snapshot_works() {
+        let mut io = TestIoBuilder::new().add_member("a".into()).finish();
+        let term = Term::new(1);
+        let entries = (0..3).map(|_| LogEntry::Noop { term }).collect();
+        io.save_log_suffix(&LogSuffix {
+            head: LogPosition::default(),
+            entries,
+        });
+
+        let tail = LogPosition {
+            prev_term: Term::new(2),
+            index: LogIndex::new(5),
+        };
+        let config = io.cluster.clone();
+        io.reset_log_to_snapshot(LogPrefix {
+            tail,
+            config,
+            snapshot: vec![1],
+        });
+        {
+            let store = io.store.lock().unwrap();
+            assert!(store.suffix.entries.is_empty());
+            assert_eq!(store.suffix.head, tail);
+        }
+        assert_eq!(
+            io.last_log_position(),
+            Some((LogIndex::new(4), Term::new(2)))
+        );
+
+        match io.load_log(LogIndex::new(0), None).wait() {
+            Ok(Log::Prefix(prefix)) => assert_eq!(prefix.tail, tail),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn recv_counts_works() -> Result<()> {
+        let network = TestNetwork::new(TestClock::default());
+        let mut a = TestIoBuilder::new()
+            .network(NodeId::new("a"), network.clone())
+            .finish();
+        let mut b = TestIoBuilder::new()
+            .network(NodeId::new("b"), network.clone())
+            .finish();
+        let mut c = TestIoBuilder::new()
+            .network(NodeId::new("c"), network)
+            .finish();
+        assert_eq!(a.received_from(), None);
+
+        b.send_message(vote_reply("b", "a", 1));
+        c.send_message(vote_reply("c", "a", 1));
+        b.send_message(vote_reply("b", "a", 1));
+        while track!(a.try_recv_message())?.is_some() {}
+
+        assert_eq!(a.received_from(), Some(&NodeId::new("b")));
+        assert_eq!(a.recv_counts().get(&NodeId::new("b")), Some(&2));
+        assert_eq!(a.recv_counts().get(&NodeId::new("c")), Some(&1));
+        assert_eq!(a.recv_counts().get(&NodeId::new("a")), None);
+        Ok(())
+    }
+
+    #[test]
+    fn channel_depth_works() -> Result<()> {
+        let network = TestNetwork::new(TestClock::default());
+        let mut a = TestIoBuilder::new()
+            .network(NodeId::new("a"), network.clone())
+            .finish();
+        let mut b = TestIoBuilder::new()
+            .network(NodeId::new("b"), network)
+            .finish();
+        assert_eq!(a.channel_depth(), 0);
+
+        for _ in 0..100 {
+            b.send_message(vote_reply("b", "a", 1));
+        }
+        assert_eq!(a.channel_depth(), 100);
+        track!(a.try_recv_message())?;
+        assert_eq!(a.channel_depth(), 99);
+
+        assert_eq!(a.drain_channel(), 99);
+        assert_eq!(a.channel_depth(), 0);
+        assert_eq!(track!(a.try_recv_message())?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn wire_roundtrip_works() -> Result<()> {
+        let network = TestNetwork::new(TestClock::default());
+        let mut a = TestIoBuilder::new()
+            .network(NodeId::new("a"), network.clone())
+            .finish();
+        let mut b = TestIoBuilder::new()
+            .network(NodeId::new("b"), network)
+            .finish();
+        a.wire_roundtrip = true;
+
+        let term = Term::new(3);
+        let config = ClusterConfig::new(vec!["a".into(), "b".into()].into_iter().collect())
+            .with_learners(vec!["c".into()].into_iter().collect())
+            .start_config_change(vec!["b".into()].into_iter().collect());
+        assert!(config.learners().contains(&"c".into()));
+        let message: Message = AppendEntriesCall {
+            header: MessageHeader {
+                sender: "a".into(),
+                destination: "b".into(),
+                seq_no: SequenceNumber::new(10),
+                term,
+            },
+            committed_log_tail: LogIndex::new(5),
+            suffix: LogSuffix {
+                head: LogPosition {
+                    prev_term: Term::new(2),
+                    index: LogIndex::new(5),
+                },
+                entries: vec![
+                    LogEntry::Noop { term },
+                    LogEntry::Config { term, config },
+                    LogEntry::Command {
+                        term,
+                        command: vec![1, 2, 3],
+                    },
+                ],
+            },
+        }
+        .into();
+        assert_eq!(track!(decode_message(&encode_message(&message)))?, message);
+
+        a.send_message(message.clone());
+        assert_eq!(track!(b.try_recv_message())?, Some(message));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn compressed_wire_roundtrip_works() -> Result<()> {
+        let network = TestNetwork::new(TestClock::default());
+        let mut a = TestIoBuilder::new()
+            .network(NodeId::new("a"), network.clone())
+            .finish();
+        let mut b = TestIoBuilder::new()
+            .network(NodeId::new("b"), network)
+            .finish();
+        a.wire_roundtrip = true;
+        a.compressor = Some(TestCompressor(Arc::new(LzCompressor::new())));
+
+        let term = Term::new(3);
+        let entries = (0..1000)
+            .map(|i| LogEntry::Command {
+                term,
+                command: vec![(i % 10) as u8; 64],
+            })
+            .collect();
+        let message: Message = AppendEntriesCall {
+            header: MessageHeader {
+                sender: "a".into(),
+                destination: "b".into(),
+                seq_no: SequenceNumber::new(1),
+                term,
+            },
+            committed_log_tail: LogIndex::new(0),
+            suffix: LogSuffix {
+                head: LogPosition::default(),
+                entries,
+            },
+        }
+        .into();
+
+        a.send_message(message.clone());
+        assert_eq!(track!(b.try_recv_message())?, Some(message.clone()));
+        assert!(a.compressed_bytes < encode_message(&message).len() as u64 / 10);
+        Ok(())
+    }
+
+    #[test]
+    fn fair_delivery_works() -> Result<()> {
+        let network = TestNetwork::new(TestClock::default());
+        let mut a = TestIoBuilder::new()
+            .network(NodeId::new("a"), network.clone())
+            .finish();
+        let mut b = TestIoBuilder::new()
+            .network(NodeId::new("b"), network.clone())
+            .finish();
+        let mut c = TestIoBuilder::new()
+            .network(NodeId::new("c"), network)
+            .finish();
+        a.fair_delivery = true;
+
+        for _ in 0..3 {
+            b.send_message(vote_reply("b", "a", 1));
+        }
+        for _ in 0..3 {
+            c.send_message(vote_reply("c", "a", 1));
+        }
+        let mut senders = Vec::new();
+        while let Some(m) = track!(a.try_recv_message())? {
+            senders.push(m.header().sender.as_str().to_owned());
+        }
+        assert_eq!(senders, ["b", "c", "b", "c", "b", "c"]);
+        Ok(())
+    }
+
+    #[test]
+    fn connected_peers_works() {
+        let network = TestNetwork::new(TestClock::default());
+        let ios = ["a", "b", "c"]
+            .iter()
+            .map(|id| {
+                TestIoBuilder::new()
+                    .network(NodeId::new(*id), network.clone())
+                    .finish()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ios[0].connected_peers(),
+            Some(vec![NodeId::new("b"), NodeId::new("c")])
+        );
+
+        network.disconnect(&NodeId::new("c"));
+        assert_eq!(ios[0].connected_peers(), Some(vec![NodeId::new("b")]));
+        assert_eq!(ios[1].connected_peers(), Some(vec![NodeId::new("a")]));
+
+        // 自身が切断された場合には、どのノードとも疎通できない
+        network.disconnect(&NodeId::new("a"));
+        assert_eq!(ios[0].connected_peers(), Some(Vec::new()));
+        assert_eq!(ios[0].peers(), [NodeId::new("b")]);
+
+        // ネットワークに接続されていない場合には、疎通状況は不明
+        let io = TestIoBuilder::new().finish();
+        assert_eq!(io.connected_peers(), None);
+    }
+
+    #[test]
+    fn replay_buffer_works() {
+        let mut io = TestIoBuilder::new().replay_buffer(2).finish();
+        for term in 1..=3 {
+            io.send_message(vote_reply("a", "b", term));
+        }
+
+        // 直近の二つのみが再送され、以降のものは逐次受信できる
+        let (history, rx) = io.attach_observer();
+        assert_eq!(history, [vote_reply("a", "b", 2), vote_reply("a", "b", 3)]);
+        io.send_message(vote_reply("a", "b", 4));
+        io.send_message(vote_reply("a", "c", 4));
+        assert_eq!(
+            rx.try_iter().collect::<Vec<_>>(),
+            [vote_reply("a", "b", 4), vote_reply("a", "c", 4)]
+        );
+
+        let (history, _) = io.attach_observer();
+        assert_eq!(history, [vote_reply("a", "b", 4), vote_reply("a", "c", 4)]);
+    }
+
+    #[test]
+    fn strict_term_filtering_works() -> Result<()> {
+        let network = TestNetwork::new(TestClock::default());
+        let mut a = TestIoBuilder::new()
+            .network(NodeId::new("a"), network.clone())
+            .finish();
+        let mut b = TestIoBuilder::new()
+            .network(NodeId::new("b"), network)
+            .finish();
+        a.save_ballot(Ballot {
+            term: Term::new(5),
+            voted_for: "a".into(),
+        });
+
+        // フィルタリングが無効な場合は、古い`Term`のメッセージも受信する
+        b.send_message(vote_reply("b", "a", 3));
+        assert!(track!(a.try_recv_message())?.is_some());
+        assert_eq!(a.received.len(), 1);
+
+        a.strict_term_filtering = true;
+        b.send_message(vote_reply("b", "a", 3));
+        b.send_message(vote_reply("b", "a", 5));
+        let message = track!(a.try_recv_message())?.expect("Never fails");
+        assert_eq!(message.header().term, Term::new(5));
+        assert!(track!(a.try_recv_message())?.is_none());
+        assert_eq!(a.received.len(), 2);
+        assert_eq!(a.received[1].header().term, Term::new(5));
+        Ok(())
+    }
+
+    #[test]
+    fn max_term_gap_works() -> Result<()> {
+        let network = TestNetwork::new(TestClock::default());
+        let mut a = TestIoBuilder::new()
+            .network(NodeId::new("a"), network.clone())
+            .finish();
+        let mut b = TestIoBuilder::new()
+            .network(NodeId::new("b"), network)
+            .finish();
+        a.save_ballot(Ballot {
+            term: Term::new(5),
+            voted_for: "a".into(),
+        });
+
+        // 閾値が設定されていない場合は、極端に大きな`Term`のメッセージも受信する
+        b.send_message(vote_reply("b", "a", 1005));
+        assert!(track!(a.try_recv_message())?.is_some());
+
+        a.max_term_gap = Some(100);
+        b.send_message(vote_reply("b", "a", 1005));
+        b.send_message(vote_reply("b", "a", 105));
+        let message = track!(a.try_recv_message())?.expect("Never fails");
+        assert_eq!(message.header().term, Term::new(105));
+        assert!(track!(a.try_recv_message())?.is_none());
+        assert_eq!(a.received.len(), 2);
+        Ok(())
+    }
 }
@@ -14,15 +14,18 @@ extern crate prometrics;
 extern crate trackable;
 
 pub use crate::error::{Error, ErrorKind};
-pub use crate::io::Io;
-pub use crate::replicated_log::{Event, ReplicatedLog};
+pub use crate::io::{Io, SaveBallotAndLog};
+pub use crate::replicated_log::{Event, LeaseRead, ReplicatedLog};
 
 pub mod cluster;
+#[cfg(feature = "compression")]
+pub mod compression;
 pub mod election;
 pub mod log;
 pub mod message;
 pub mod metrics;
 pub mod node;
+pub mod state_machine;
 
 mod error;
 mod io;
@@ -2,6 +2,7 @@ use std::collections::VecDeque;
 use trackable::error::ErrorKindExt;
 
 use crate::cluster::ClusterConfig;
+use crate::election::Term;
 use crate::log::{LogEntry, LogIndex, LogPosition, LogPrefix, LogSuffix};
 use crate::{ErrorKind, Result};
 
@@ -56,10 +57,31 @@ impl LogHistory {
     }
 
     /// ローカルログに記録された最新のクラスタ構成を返す.
+    ///
+    /// Raftの規則に従い、合意(i.e., 選挙やコミット)の判定には、
+    /// コミット済みかどうかに関わらず、この構成が使用される.
     pub fn config(&self) -> &ClusterConfig {
         &self.last_record().config
     }
 
+    /// コミット済みの領域に記録された最新のクラスタ構成を返す.
+    pub fn committed_config(&self) -> &ClusterConfig {
+        self.get_record(self.committed_tail.index)
+            .map_or_else(|| &self.records[0].config, |r| &r.config)
+    }
+
+    /// 未コミットの領域に、コミット済みのものとは異なるクラスタ構成が記録されている場合には、それを返す.
+    ///
+    /// 提案された構成変更は、コミットされた時点で`committed_config`に反映される.
+    pub fn pending_config(&self) -> Option<&ClusterConfig> {
+        let latest = self.config();
+        if latest != self.committed_config() {
+            Some(latest)
+        } else {
+            None
+        }
+    }
+
     /// 最後に追加された`HistoryRecord`を返す.
     pub fn last_record(&self) -> &HistoryRecord {
         self.records.back().expect("Never fails")
@@ -77,6 +99,38 @@ impl LogHistory {
         None
     }
 
+    /// `index`の直前のエントリの`Term`と、ローカルログ上でその`Term`のエントリ群が始まる位置を返す.
+    ///
+    /// スナップショット地点以前は考慮されないので、開始位置がログの先頭位置より前になることはない.
+    ///
+    /// 既に削除された領域が指定された場合には`None`が返される.
+    pub fn term_start(&self, index: LogIndex) -> Option<(Term, LogIndex)> {
+        let i = self.records.iter().rposition(|r| r.head.index <= index)?;
+        let term = self.records[i].head.prev_term;
+        let start = self
+            .records
+            .iter()
+            .take(i + 1)
+            .rposition(|r| r.head.prev_term != term)
+            .map_or(self.head().index, |j| self.records[j + 1].head.index - 1);
+        Some((term, start))
+    }
+
+    /// ローカルログ上で`term`のエントリ群が終わる位置(i.e., 最後のエントリの次の位置)を返す.
+    ///
+    /// ログ上に`term`のレコードが存在しない場合には`None`が返される.
+    pub fn term_end(&self, term: Term) -> Option<LogIndex> {
+        let i = self
+            .records
+            .iter()
+            .rposition(|r| r.head.prev_term == term)?;
+        let end = self
+            .records
+            .get(i + 1)
+            .map_or(self.appended_tail.index, |r| r.head.index - 1);
+        Some(end)
+    }
+
     /// `suffix`がローカルログに追記されたことを記録する.
     pub fn record_appended(&mut self, suffix: &LogSuffix) -> Result<()> {
         let entries_offset = if self.appended_tail.index <= suffix.head.index {
@@ -270,3 +324,64 @@ impl HistoryRecord {
         HistoryRecord { head, config }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::{ClusterMembers, ClusterState};
+    use crate::node::NodeId;
+
+    fn members(ids: &[&str]) -> ClusterMembers {
+        ids.iter().map(|id| NodeId::new(*id)).collect()
+    }
+
+    #[test]
+    fn quorum_uses_latest_config_in_log() -> Result<()> {
+        let stable = ClusterConfig::new(members(&["a", "b", "c"]));
+        let joint = ClusterConfig::with_state(
+            members(&["c", "d", "e"]),
+            stable.primary_members().clone(),
+            ClusterState::Joint,
+        );
+        let mut history = LogHistory::new(stable.clone());
+        let term = Term::new(1);
+        track!(history.record_appended(&LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![
+                LogEntry::Noop { term },
+                LogEntry::Config {
+                    term,
+                    config: joint.clone(),
+                },
+            ],
+        }))?;
+        track!(history.record_committed(LogIndex::new(1)))?;
+
+        // 構成変更エントリは未コミット
+        assert_eq!(*history.committed_config(), stable);
+        assert_eq!(history.pending_config(), Some(&joint));
+        assert_eq!(*history.config(), joint);
+
+        // 旧メンバのみが複製済みの地点は、コミット済みの構成では過半数だが、最新の構成ではそうではない
+        let match_index = |id: &NodeId| {
+            if members(&["a", "b", "c"]).contains(id) {
+                LogIndex::new(2)
+            } else {
+                LogIndex::new(0)
+            }
+        };
+        assert_eq!(
+            track!(history.committed_config().committable_index(match_index))?,
+            LogIndex::new(2)
+        );
+        assert_eq!(
+            track!(history.config().committable_index(match_index))?,
+            LogIndex::new(0)
+        );
+
+        track!(history.record_committed(LogIndex::new(2)))?;
+        assert_eq!(*history.committed_config(), joint);
+        assert_eq!(history.pending_config(), None);
+        Ok(())
+    }
+}
@@ -30,7 +30,7 @@ impl From<LogSuffix> for Log {
 }
 
 /// ログの前半部分 (i.e., スナップショット).
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct LogPrefix {
     /// 前半部分の終端位置.
     ///
@@ -52,7 +52,7 @@ pub struct LogPrefix {
 /// ただし、このデータ構造自体は、常に追記的なアクセスのために利用され、
 /// "ログの途中の一部だけを更新する"といった操作は発生しないので、
 /// "常にログの末尾に対して適用される"的な意味合いで`Suffix`と付けている.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct LogSuffix {
     /// ログの開始位置.
     ///
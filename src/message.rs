@@ -8,7 +8,7 @@ use crate::log::{LogIndex, LogPosition, LogPrefix, LogSuffix};
 use crate::node::NodeId;
 
 /// RPC用のメッセージ全般.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 #[allow(missing_docs)]
 pub enum Message {
     RequestVoteCall(RequestVoteCall),
@@ -16,6 +16,7 @@ pub enum Message {
     AppendEntriesCall(AppendEntriesCall),
     AppendEntriesReply(AppendEntriesReply),
     InstallSnapshotCast(InstallSnapshotCast),
+    LeaveCast(LeaveCast),
 }
 impl Message {
     /// メッセージのヘッダを返す.
@@ -26,6 +27,7 @@ impl Message {
             Message::AppendEntriesCall(m) => &m.header,
             Message::AppendEntriesReply(m) => &m.header,
             Message::InstallSnapshotCast(m) => &m.header,
+            Message::LeaveCast(m) => &m.header,
         }
     }
 
@@ -46,6 +48,9 @@ impl Message {
             Message::InstallSnapshotCast(m) => {
                 m.header.destination = dst.clone();
             }
+            Message::LeaveCast(m) => {
+                m.header.destination = dst.clone();
+            }
         }
     }
 }
@@ -74,9 +79,14 @@ impl From<InstallSnapshotCast> for Message {
         Message::InstallSnapshotCast(f)
     }
 }
+impl From<LeaveCast> for Message {
+    fn from(f: LeaveCast) -> Self {
+        Message::LeaveCast(f)
+    }
+}
 
 /// メッセージのヘッダ.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct MessageHeader {
     /// メッセージの送信元.
     pub sender: NodeId,
@@ -93,7 +103,7 @@ pub struct MessageHeader {
 }
 
 /// `RequestVoteRPC`の要求メッセージ.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct RequestVoteCall {
     /// メッセージヘッダ.
     pub header: MessageHeader,
@@ -103,7 +113,7 @@ pub struct RequestVoteCall {
 }
 
 /// `RequestVoteRPC`の応答メッセージ.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct RequestVoteReply {
     /// メッセージヘッダ.
     pub header: MessageHeader,
@@ -113,7 +123,7 @@ pub struct RequestVoteReply {
 }
 
 /// `AppendEntriesRPC`の要求メッセージ.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct AppendEntriesCall {
     /// メッセージヘッダ.
     pub header: MessageHeader,
@@ -126,7 +136,10 @@ pub struct AppendEntriesCall {
 }
 
 /// `AppendEntriesRPC`の応答メッセージ.
-#[derive(Debug, Clone)]
+///
+/// 将来的にフィールドが追加される可能性があるので、クレートの外部で生成する場合には`new`を使用すること.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub struct AppendEntriesReply {
     /// メッセージヘッダ.
     pub header: MessageHeader,
@@ -145,6 +158,38 @@ pub struct AppendEntriesReply {
     /// followerの`log_tail`が遅れていたとしても、
     /// リーダはログの同期のための追加のメッセージ送信を行わない.
     pub busy: bool,
+
+    /// 応答者のログが、リーダのログと一致しない可能性がある場合のヒント.
+    ///
+    /// リーダは、これを使って同期点の探索を省略することができる.
+    pub conflict: Option<ConflictHint>,
+}
+impl AppendEntriesReply {
+    /// 不一致箇所のヒントを持たない応答メッセージを生成する.
+    pub fn new(header: MessageHeader, log_tail: LogPosition, busy: bool) -> Self {
+        AppendEntriesReply {
+            header,
+            log_tail,
+            busy,
+            conflict: None,
+        }
+    }
+
+    /// 不一致箇所のヒントを設定する.
+    pub fn with_conflict(mut self, hint: ConflictHint) -> Self {
+        self.conflict = Some(hint);
+        self
+    }
+}
+
+/// `AppendEntriesReply`に付与される、ログの不一致箇所に関するヒント.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConflictHint {
+    /// 応答者のログの`log_tail`の直前のエントリの`Term`.
+    pub term: Term,
+
+    /// 応答者のログ上で、`term`のエントリ群が始まる位置.
+    pub first_index: LogIndex,
 }
 
 /// `InstallSnapshotRPC`用のメッセージ.
@@ -152,7 +197,7 @@ pub struct AppendEntriesReply {
 /// 論文中では、これも他のRPC同様に"要求・応答"形式となっているが、
 /// 他のRPCとは異なり、これに関しては本質的には応答は不要なので、
 /// ここでは一方的な送信のみをサポートしている.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct InstallSnapshotCast {
     /// メッセージヘッダ.
     pub header: MessageHeader,
@@ -161,6 +206,18 @@ pub struct InstallSnapshotCast {
     pub prefix: LogPrefix,
 }
 
+/// ノードの停止を通知するためのメッセージ.
+///
+/// 停止するノードが、他のノードに対して一方的に送信する.
+/// 受信したノードは、タイムアウトを待つことなく、送信元を到達不能なノードとして扱うことができる.
+///
+/// なお、これはRaftの論文には存在しない、このクレート独自のメッセージである.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeaveCast {
+    /// メッセージヘッダ.
+    pub header: MessageHeader,
+}
+
 /// メッセージのシーケンス番号.
 ///
 /// この番号はノード毎に管理され、要求系のメッセージ送信の度にインクリメントされる.
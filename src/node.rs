@@ -1,7 +1,12 @@
 //! ノード関連の構成要素.
 use crate::election::{Ballot, Role};
+use crate::log::LogIndex;
+use crate::message::SequenceNumber;
 
 /// ノードのID.
+///
+/// IDの大小関係は、対応する文字列の辞書順で決まる
+/// (e.g., `"node10" < "node2"`).
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct NodeId(String);
 impl NodeId {
@@ -36,6 +41,34 @@ impl From<NodeId> for String {
     }
 }
 
+/// リーダから見た、フォロワーのログの複製状況.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FollowerStatus {
+    /// フォロワーのID.
+    pub id: NodeId,
+
+    /// フォロワーが投票権を持つかどうか.
+    ///
+    /// 構成変更中の学習者(i.e., 新構成にのみ属するメンバ)の場合は`false`となる.
+    pub voter: bool,
+
+    /// フォロワーのログに複製済みであることが確認できている領域の終端.
+    ///
+    /// 同期点の探索中の場合には`None`となる.
+    pub match_tail: Option<LogIndex>,
+
+    /// 次にフォロワーに送信するエントリのインデックス.
+    pub next_index: LogIndex,
+
+    /// フォロワーから最後に受信した応答のシーケンス番号.
+    ///
+    /// 最終通信時刻の代わりに使用される(値が大きいほど、最近に通信したことを示す).
+    pub last_seq_no: SequenceNumber,
+
+    /// フォロワーのログが、リーダのログに追いついているかどうか.
+    pub caught_up: bool,
+}
+
 /// ノードの各種情報を保持するためのデータ構造.
 #[derive(Debug, Clone)]
 pub struct Node {
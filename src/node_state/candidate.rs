@@ -1,7 +1,7 @@
 use futures::{Async, Future};
 use std::collections::HashSet;
 
-use super::{Common, NextState};
+use super::{Common, NextState, Persist};
 use crate::election::Role;
 use crate::message::{Message, RequestVoteReply};
 use crate::node::NodeId;
@@ -17,7 +17,7 @@ use crate::{Io, Result};
 /// - 3-b. タイムアウトに達したら、次の選挙を開始して再び立候補
 pub struct Candidate<IO: Io> {
     followers: HashSet<NodeId>,
-    init: Option<IO::SaveBallot>,
+    init: Option<Persist<IO, IO::SaveBallot>>,
 }
 impl<IO: Io> Candidate<IO> {
     pub fn new(common: &mut Common<IO>) -> Self {
@@ -38,9 +38,9 @@ impl<IO: Io> Candidate<IO> {
     ) -> Result<NextState<IO>> {
         if let Message::RequestVoteReply(RequestVoteReply { voted: true, .. }) = message {
             self.followers.insert(message.header().sender.clone());
-            let is_elected = common
+            let is_elected = track!(common
                 .config()
-                .consensus_value(|n| self.followers.contains(n));
+                .consensus_value(|n| self.followers.contains(n)))?;
             if is_elected {
                 return Ok(Some(common.transit_to_leader()));
             }
@@ -1,5 +1,6 @@
 use futures::{Async, Future, Poll};
-use std::collections::VecDeque;
+use std::collections::{BTreeSet, VecDeque};
+use std::time::{Duration, Instant};
 
 use self::rpc_builder::{RpcCallee, RpcCaller};
 use super::candidate::Candidate;
@@ -12,7 +13,7 @@ use crate::log::{Log, LogHistory, LogIndex, LogPosition, LogPrefix, LogSuffix};
 use crate::message::{Message, MessageHeader, SequenceNumber};
 use crate::metrics::NodeStateMetrics;
 use crate::node::{Node, NodeId};
-use crate::{Error, ErrorKind, Event, Io, Result};
+use crate::{Error, ErrorKind, Event, Io, Result, SaveBallotAndLog};
 
 mod rpc_builder;
 
@@ -21,12 +22,19 @@ pub struct Common<IO: Io> {
     local_node: Node,
     history: LogHistory,
     timeout: IO::Timeout,
+    election_deferred: bool,
     events: VecDeque<Event>,
     io: IO,
     unread_message: Option<Message>,
     seq_no: SequenceNumber,
     load_committed: Option<IO::LoadLog>,
     install_snapshot: Option<InstallSnapshot<IO>>,
+    unreachable_nodes: BTreeSet<NodeId>,
+    max_inflight: Option<usize>,
+    fast_backoff: bool,
+    check_quorum: Option<usize>,
+    lease_duration: Option<Duration>,
+    config_change_started_at: Option<Instant>,
     metrics: NodeStateMetrics,
 }
 impl<IO> Common<IO>
@@ -49,9 +57,16 @@ where
             unread_message: None,
             seq_no: SequenceNumber::new(0),
             timeout,
+            election_deferred: false,
             events: VecDeque::new(),
             load_committed: None,
             install_snapshot: None,
+            unreachable_nodes: BTreeSet::new(),
+            max_inflight: None,
+            fast_backoff: false,
+            check_quorum: None,
+            lease_duration: None,
+            config_change_started_at: None,
             metrics,
         }
     }
@@ -71,6 +86,59 @@ where
         self.history.committed_tail()
     }
 
+    /// 停止通知(`LeaveCast`)を受信したために、到達不能として扱っているノード群を返す.
+    ///
+    /// 停止したノードから再びメッセージを受信した場合には、そのノードはこの集合から除外される.
+    pub fn unreachable_nodes(&self) -> &BTreeSet<NodeId> {
+        &self.unreachable_nodes
+    }
+
+    /// 各フォロワーに対して、応答を待たずに送信可能な`AppendEntriesCall`の最大数を返す.
+    ///
+    /// `None`の場合は無制限.
+    pub fn max_inflight(&self) -> Option<usize> {
+        self.max_inflight
+    }
+
+    /// 各フォロワーに対して、応答を待たずに送信可能な`AppendEntriesCall`の最大数を設定する.
+    pub fn set_max_inflight(&mut self, max: Option<usize>) {
+        self.max_inflight = max;
+    }
+
+    /// フォロワーとの同期点の探索時に、不一致だった`Term`の区間をまとめて読み飛ばすかどうかを返す.
+    pub fn fast_backoff(&self) -> bool {
+        self.fast_backoff
+    }
+
+    /// フォロワーとの同期点の探索時に、不一致だった`Term`の区間をまとめて読み飛ばすかどうかを設定する.
+    pub fn set_fast_backoff(&mut self, enabled: bool) {
+        self.fast_backoff = enabled;
+    }
+
+    /// リーダが、過半数からの応答が無いまま、何回ハートビートを送信したら退くかを返す.
+    ///
+    /// `None`の場合は、退くことはない.
+    pub fn check_quorum(&self) -> Option<usize> {
+        self.check_quorum
+    }
+
+    /// リーダが、過半数からの応答が無いまま、何回ハートビートを送信したら退くかを設定する.
+    pub fn set_check_quorum(&mut self, rounds: Option<usize>) {
+        self.check_quorum = rounds;
+    }
+
+    /// 過半数から応答を得たハートビートの送信時刻を起点とする、リーダリースの期間を返す.
+    ///
+    /// `None`の場合は、リースは付与されない.
+    pub fn lease_duration(&self) -> Option<Duration> {
+        self.lease_duration
+    }
+
+    /// 過半数から応答を得たハートビートの送信時刻を起点とする、リーダリースの期間を設定する.
+    pub fn set_lease_duration(&mut self, duration: Option<Duration>) {
+        self.lease_duration = duration;
+    }
+
     /// 現在の`Term` (選挙番号) を返す.
     pub fn term(&self) -> Term {
         self.local_node.ballot.term
@@ -86,9 +154,33 @@ where
         track!(self.history.record_appended(suffix))
     }
 
+    /// `IO`に、ポーリングのループが一周したことを通知する.
+    ///
+    /// 合わせて、構成変更の開始と完了を検知して、開始時刻(`Io::now`)を記録ないし破棄する.
+    pub fn tick(&mut self) {
+        if !self.config().is_change_in_flight() {
+            self.config_change_started_at = None;
+        } else if self.config_change_started_at.is_none() {
+            self.config_change_started_at = Some(self.io.now());
+        }
+        self.io.tick();
+    }
+
+    /// 進行中の構成変更を、ローカルノードが最初に観測した時刻を返す.
+    ///
+    /// 安定状態の場合には`None`が返される.
+    pub fn config_change_started_at(&self) -> Option<Instant> {
+        self.config_change_started_at
+    }
+
     /// ログのコミットイベントを処理する.
     pub fn handle_log_committed(&mut self, new_tail: LogIndex) -> Result<()> {
-        track!(self.history.record_committed(new_tail))
+        let old_tail = self.history.committed_tail().index;
+        track!(self.history.record_committed(new_tail))?;
+        if old_tail < new_tail {
+            self.io.on_commit(new_tail);
+        }
+        Ok(())
     }
 
     /// ローカルログのロールバックイベントを処理する.
@@ -207,6 +299,15 @@ where
         RoleState::Follower(Follower::new(self, pending_vote))
     }
 
+    /// リーダの座を退いて、`Follower`状態に遷移する.
+    ///
+    /// 投票状況は変更しないので、同じ`Term`の間に、他の候補者に投票することはない.
+    pub fn step_down(&mut self) -> RoleState<IO> {
+        self.metrics.transit_to_follower_total.increment();
+        self.set_role(Role::Follower);
+        RoleState::Follower(Follower::new(self, None))
+    }
+
     /// 新しいリーダーが選出されたことを通知する.
     pub fn notify_new_leader_elected(&mut self) {
         self.events.push_back(Event::NewLeaderElected);
@@ -239,13 +340,37 @@ where
     }
 
     /// ローカルログの末尾部分に`suffix`を追記する.
-    pub fn save_log_suffix(&mut self, suffix: &LogSuffix) -> IO::SaveLog {
-        self.io.save_log_suffix(suffix)
+    pub fn save_log_suffix(&mut self, suffix: &LogSuffix) -> Persist<IO, IO::SaveLog> {
+        let future = self.io.save_log_suffix(suffix);
+        Persist::new(&mut self.io, future)
     }
 
     /// 現在の投票状況を保存する.
-    pub fn save_ballot(&mut self) -> IO::SaveBallot {
-        self.io.save_ballot(self.local_node.ballot.clone())
+    pub fn save_ballot(&mut self) -> Persist<IO, IO::SaveBallot> {
+        let future = self.io.save_ballot(self.local_node.ballot.clone());
+        Persist::new(&mut self.io, future)
+    }
+
+    /// ローカルログの末尾部分に`suffix`を追記する.
+    ///
+    /// `with_ballot`が`true`の場合には、現在の投票状況も合わせて保存する.
+    pub fn save_ballot_and_log(
+        &mut self,
+        with_ballot: bool,
+        suffix: &LogSuffix,
+    ) -> Persist<IO, SaveBallotAndLog<IO::SaveBallot, IO::SaveLog>> {
+        let ballot = if with_ballot {
+            Some(self.local_node.ballot.clone())
+        } else {
+            None
+        };
+        let future = self.io.save_ballot_and_log(ballot, suffix);
+        Persist::new(&mut self.io, future)
+    }
+
+    /// 未処理の受信メッセージとして、`AppendEntriesCall`を保持しているかどうかを判定する.
+    pub fn has_unread_append_entries(&self) -> bool {
+        matches!(self.unread_message, Some(Message::AppendEntriesCall(_)))
     }
 
     /// 以前の投票状況を復元する.
@@ -256,6 +381,21 @@ where
     /// 指定されたロール用のタイムアウトを設定する.
     pub fn set_timeout(&mut self, role: Role) {
         self.timeout = self.io.create_timeout(role);
+        self.election_deferred = false;
+    }
+
+    /// フォロワー用のタイムアウトを設定し直して、立候補を一回分だけ遅らせる.
+    ///
+    /// 遅らせた後のタイムアウトまでにリーダからのメッセージを受信しなかった場合には、
+    /// `is_election_deferred`が`true`となり、それ以上は遅らせずに立候補する必要がある.
+    pub fn defer_election(&mut self) {
+        self.set_timeout(Role::Follower);
+        self.election_deferred = true;
+    }
+
+    /// 現在のタイムアウトが、`defer_election`によって設定されたものかどうかを判定する.
+    pub fn is_election_deferred(&self) -> bool {
+        self.election_deferred
     }
 
     /// タイムアウトに達していないかを確認する.
@@ -293,6 +433,13 @@ where
 
     /// 受信メッセージに対する共通的な処理を実行する.
     pub fn handle_message(&mut self, message: Message) -> HandleMessageResult<IO> {
+        if let Message::LeaveCast(ref m) = message {
+            // 停止通知は選挙とは無関係なので、`Term`に関わらず記録だけを行う
+            self.unreachable_nodes.insert(m.header.sender.clone());
+            return HandleMessageResult::Handled(None);
+        }
+        self.unreachable_nodes.remove(&message.header().sender);
+
         if self.local_node.role == Role::Leader
             && !self.config().is_known_node(&message.header().sender)
         {
@@ -305,13 +452,19 @@ where
         } else if message.header().term > self.local_node.ballot.term {
             // b) 相手のtermの方が大きい => 新しい選挙が始まっているので追従する
             let is_follower = self.local_node.ballot.voted_for != self.local_node.id;
-            if is_follower && self.local_node.ballot.voted_for != message.header().sender {
+            if is_follower
+                && !self.election_deferred
+                && self.local_node.ballot.voted_for != message.header().sender
+            {
                 // リーダをフォロー中(i.e., 定期的にハートビートを受信できている)の場合には、
                 // そのリーダを信じて、現在の選挙を維持する.
                 //
                 // これはクラスタ構成変更時に、旧構成のメンバによって、延々と新選挙の開始が繰り返されてしまう
                 // 可能性がある問題への対処となる.
                 // この問題の詳細は論文の「6 Cluster membership changes」の"The third issue is ..."部分を参照のこと.
+                //
+                // なお、立候補を遅らせている間は、既にリーダからのハートビートが途絶えているので、
+                // 優先度のより高いノードが開始した選挙に追従する.
                 return HandleMessageResult::Handled(None);
             }
 
@@ -449,8 +602,39 @@ struct SnapshotSummary {
     config: ClusterConfig,
 }
 
+/// 保存処理の`Future`に、`Io::create_persist_timeout`によるタイムアウトを付与したもの.
+pub struct Persist<IO: Io, F> {
+    future: F,
+    timeout: Option<IO::Timeout>,
+}
+impl<IO: Io, F> Persist<IO, F> {
+    fn new(io: &mut IO, future: F) -> Self {
+        let timeout = io.create_persist_timeout();
+        Persist { future, timeout }
+    }
+}
+impl<IO: Io, F> Future for Persist<IO, F>
+where
+    F: Future<Item = (), Error = Error>,
+{
+    type Item = ();
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Async::Ready(()) = track!(self.future.poll())? {
+            return Ok(Async::Ready(()));
+        }
+        if let Async::Ready(Some(())) = track!(self.timeout.poll())? {
+            track_panic!(
+                ErrorKind::Other,
+                "Persist operation did not complete within the timeout"
+            );
+        }
+        Ok(Async::NotReady)
+    }
+}
+
 struct InstallSnapshot<IO: Io> {
-    future: IO::SaveLog,
+    future: Persist<IO, IO::SaveLog>,
     summary: SnapshotSummary,
 }
 impl<IO: Io> InstallSnapshot<IO> {
@@ -460,6 +644,7 @@ impl<IO: Io> InstallSnapshot<IO> {
             config: prefix.config.clone(),
         };
         let future = common.io.save_log_prefix(prefix);
+        let future = Persist::new(&mut common.io, future);
         InstallSnapshot { future, summary }
     }
 }
@@ -1,6 +1,10 @@
+use std::collections::BTreeSet;
+
 use super::Common;
 use crate::log::{LogPosition, LogPrefix, LogSuffix};
-use crate::message::{self, AppendEntriesReply, Message, MessageHeader, SequenceNumber};
+use crate::message::{
+    self, AppendEntriesReply, ConflictHint, Message, MessageHeader, SequenceNumber,
+};
 use crate::node::NodeId;
 use crate::Io;
 
@@ -25,9 +29,16 @@ impl<'a, IO: 'a + Io> RpcCaller<'a, IO> {
             voted: true,
         }
         .into();
-        self.broadcast(request, self_reply);
+        self.broadcast(request, self_reply, &BTreeSet::new());
+    }
+    pub fn broadcast_append_entries(self, suffix: LogSuffix) {
+        self.broadcast_append_entries_except(suffix, &BTreeSet::new());
     }
-    pub fn broadcast_append_entries(mut self, suffix: LogSuffix) {
+    pub fn broadcast_append_entries_except(
+        mut self,
+        suffix: LogSuffix,
+        excludes: &BTreeSet<NodeId>,
+    ) {
         let header = self.make_header(&NodeId::new(String::new())); // ブロードキャストノード時に空文字列を宛先に指定
         let request = message::AppendEntriesCall {
             header: header.clone(),
@@ -35,13 +46,8 @@ impl<'a, IO: 'a + Io> RpcCaller<'a, IO> {
             suffix,
         }
         .into();
-        let self_reply = AppendEntriesReply {
-            header,
-            log_tail: self.common.history.tail(),
-            busy: false,
-        }
-        .into();
-        self.broadcast(request, self_reply);
+        let self_reply = AppendEntriesReply::new(header, self.common.history.tail(), false).into();
+        self.broadcast(request, self_reply, excludes);
     }
     pub fn send_append_entries(mut self, peer: &NodeId, suffix: LogSuffix) {
         let message = message::AppendEntriesCall {
@@ -58,6 +64,17 @@ impl<'a, IO: 'a + Io> RpcCaller<'a, IO> {
         self.common.io.send_message(message);
     }
 
+    pub fn broadcast_leave(mut self) {
+        let header = self.make_header(&NodeId::new(String::new())); // ブロードキャストノード時に空文字列を宛先に指定
+        let mut message: Message = message::LeaveCast { header }.into();
+        for peer in self.common.history.config().members() {
+            if *peer != self.common.local_node.id {
+                message.set_destination(peer);
+                self.common.io.send_message(message.clone());
+            }
+        }
+    }
+
     fn make_header(&mut self, destination: &NodeId) -> MessageHeader {
         let seq_no = self.common.seq_no;
         self.common.seq_no = SequenceNumber::new(seq_no.as_u64() + 1);
@@ -68,12 +85,17 @@ impl<'a, IO: 'a + Io> RpcCaller<'a, IO> {
             term: self.common.local_node.ballot.term,
         }
     }
-    fn broadcast(&mut self, mut message: Message, self_reply: Message) {
+    fn broadcast(
+        &mut self,
+        mut message: Message,
+        self_reply: Message,
+        excludes: &BTreeSet<NodeId>,
+    ) {
         let mut do_self_reply = false;
         for peer in self.common.history.config().members() {
             if *peer == self.common.local_node.id {
                 do_self_reply = true;
-            } else {
+            } else if !excludes.contains(peer) {
                 message.set_destination(peer);
                 self.common.io.send_message(message.clone());
             }
@@ -99,21 +121,21 @@ impl<'a, IO: 'a + Io> RpcCallee<'a, IO> {
         self.common.io.send_message(message);
     }
     pub fn reply_append_entries(self, log_tail: LogPosition) {
-        let message = AppendEntriesReply {
-            header: self.make_header(),
-            log_tail,
-            busy: false,
-        }
-        .into();
+        let message = AppendEntriesReply::new(self.make_header(), log_tail, false).into();
         self.common.io.send_message(message);
     }
-    pub fn reply_busy(self) {
-        let message = AppendEntriesReply {
-            header: self.make_header(),
-            log_tail: self.common.history.tail(),
-            busy: true,
+
+    /// リーダのログと一致しない可能性のある`log_tail`を、不一致箇所のヒント付きで応答する.
+    pub fn reply_append_entries_conflict(self, log_tail: LogPosition) {
+        let mut reply = AppendEntriesReply::new(self.make_header(), log_tail, false);
+        if let Some((term, first_index)) = self.common.history.term_start(log_tail.index) {
+            reply = reply.with_conflict(ConflictHint { term, first_index });
         }
-        .into();
+        self.common.io.send_message(reply.into());
+    }
+    pub fn reply_busy(self) {
+        let message =
+            AppendEntriesReply::new(self.make_header(), self.common.history.tail(), true).into();
         self.common.io.send_message(message);
     }
 
@@ -1,25 +1,28 @@
 use futures::{Async, Future};
 
-use super::super::{Common, NextState, RoleState};
+use super::super::{Common, NextState, Persist, RoleState};
 use super::{Follower, FollowerIdle};
 use crate::log::LogPosition;
 use crate::message::{AppendEntriesCall, Message};
-use crate::{Io, Result};
+use crate::{Io, Result, SaveBallotAndLog};
+
+type SaveFuture<IO> = Persist<IO, SaveBallotAndLog<<IO as Io>::SaveBallot, <IO as Io>::SaveLog>>;
 
 /// ローカルログへの追記を行うフォロワーのサブ状態.
 ///
 /// `AppendEntriesCall`で送られてきたエントリの追記を行う.
+/// 投票状況が未保存の場合には、エントリ群と合わせて保存する.
 ///
 /// `AppendEntriesCall`が妥当な内容かどうかの判定や、
 /// 細かい調整処理は`FollowerIdle`内で行われ、
 /// ここが担当するのは、あくまでもログ追記処理のみ.
 pub struct FollowerAppend<IO: Io> {
-    future: Option<IO::SaveLog>,
+    future: Option<SaveFuture<IO>>,
     new_log_tail: LogPosition,
     message: AppendEntriesCall,
 }
 impl<IO: Io> FollowerAppend<IO> {
-    pub fn new(common: &mut Common<IO>, mut message: AppendEntriesCall) -> Self {
+    pub fn new(common: &mut Common<IO>, mut message: AppendEntriesCall, with_ballot: bool) -> Self {
         // メッセージ群の順序は逆転する可能性があるので、
         // それによってインデックスの巻き戻りが発生しないように調整.
         let mut new_log_tail = message.suffix.tail();
@@ -38,7 +41,7 @@ impl<IO: Io> FollowerAppend<IO> {
             // (AppendEntriesCallは、単にハートビートの用途でも使用されるので、空のケースは珍しくない)
             None
         } else {
-            Some(common.save_log_suffix(&message.suffix))
+            Some(common.save_ballot_and_log(with_ballot, &message.suffix))
         };
         FollowerAppend {
             future,
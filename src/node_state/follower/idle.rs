@@ -2,20 +2,31 @@ use std::marker::PhantomData;
 use trackable::error::ErrorKindExt;
 
 use super::super::{Common, NextState, RoleState};
-use super::{Follower, FollowerAppend, FollowerSnapshot};
+use super::{Follower, FollowerAppend, FollowerInit, FollowerSnapshot};
 use crate::log::{LogPosition, LogSuffix};
-use crate::message::{AppendEntriesCall, Message};
+use crate::message::{AppendEntriesCall, Message, MessageHeader};
 use crate::{ErrorKind, Io, Result};
 
 /// 待機中(i.e., 受信メッセージ処理が可能)なフォロワーのサブ状態.
 ///
 /// リーダから送られてきた`AppendEntriesCall`および`InstallSnapshotCast`を処理する.
+///
+/// 投票状況が未保存の場合には、受信したエントリ群を追記する際に、それらと合わせて投票状況を保存する.
+/// 追記を伴わないメッセージを受信した場合には、応答する前に、まずは投票状況のみを保存する.
 pub struct FollowerIdle<IO: Io> {
+    ballot_unsaved: bool,
     _phantom: PhantomData<IO>,
 }
 impl<IO: Io> FollowerIdle<IO> {
     pub fn new() -> Self {
         FollowerIdle {
+            ballot_unsaved: false,
+            _phantom: PhantomData,
+        }
+    }
+    pub fn with_unsaved_ballot() -> Self {
+        FollowerIdle {
+            ballot_unsaved: true,
             _phantom: PhantomData,
         }
     }
@@ -28,6 +39,11 @@ impl<IO: Io> FollowerIdle<IO> {
     ) -> Result<NextState<IO>> {
         match message {
             Message::AppendEntriesCall(m) => track!(self.handle_entries(common, m)),
+            Message::InstallSnapshotCast(m) if self.ballot_unsaved => {
+                // 投票状況のみを先に保存し、スナップショットはその後に処理する
+                let init = FollowerInit::new(common, None).with_pending_snapshot(m);
+                Ok(Some(RoleState::Follower(Follower::Init(init))))
+            }
             Message::InstallSnapshotCast(m) => {
                 if m.prefix.tail.index <= common.log_committed_tail().index {
                     // 既にコミット済みの地点のスナップショットは無視する
@@ -57,6 +73,10 @@ impl<IO: Io> FollowerIdle<IO> {
         // `AppendEntriesCall`で受け取ったエントリ群が、ローカルログの末尾に追記可能になるように調整する
 
         let local_tail = common.log().tail();
+        if self.ballot_unsaved && message.suffix.tail().index <= local_tail.index {
+            // 追記を伴わないので、投票状況のみを先に保存する
+            return Ok(Some(self.save_ballot_first(common, &message.header)));
+        }
         if message.suffix.tail().index < common.log().head().index {
             // リーダのログが、ローカルログに比べて大幅に短い (i.e., スナップショット地点以前)
             // => チャンネルに任意のメッセージ遅延を許している以上発生し得る
@@ -71,12 +91,15 @@ impl<IO: Io> FollowerIdle<IO> {
             track!(message.suffix.skip_to(common.log().head().index))?;
         }
 
+        if self.ballot_unsaved && local_tail.index < message.suffix.head.index {
+            return Ok(Some(self.save_ballot_first(common, &message.header)));
+        }
         if local_tail.index < message.suffix.head.index {
             // リーダのログが先に進み過ぎている
             // => 自分のログの末尾を伝えて、再送して貰う
             common
                 .rpc_callee(&message.header)
-                .reply_append_entries(local_tail);
+                .reply_append_entries_conflict(local_tail);
             Ok(None)
         } else {
             // リーダのログとローカルのログに重複部分があり、追記が行える可能性が高い
@@ -90,6 +113,9 @@ impl<IO: Io> FollowerIdle<IO> {
     ) -> Result<NextState<IO>> {
         // リーダとローカルのログの共通部分を探索
         let (matched, lcp) = track!(self.longest_common_prefix(common, &message.suffix))?;
+        if self.ballot_unsaved && !matched {
+            return Ok(Some(self.save_ballot_first(common, &message.header)));
+        }
         if !matched {
             // 両者が分岐している
             // => ローカルログ(の未コミット領域)をロールバックして、同期位置まで戻る
@@ -97,15 +123,25 @@ impl<IO: Io> FollowerIdle<IO> {
             track!(common.handle_log_rollbacked(new_log_tail))?;
             common
                 .rpc_callee(&message.header)
-                .reply_append_entries(new_log_tail);
+                .reply_append_entries_conflict(new_log_tail);
             Ok(None)
         } else {
             // 両者は包含関係にあるので、追記が可能
             track!(message.suffix.skip_to(lcp.index))?;
-            let next = FollowerAppend::new(common, message);
+            let next = FollowerAppend::new(common, message, self.ballot_unsaved);
             Ok(Some(RoleState::Follower(Follower::Append(next))))
         }
     }
+
+    /// 受信したメッセージにはビジー応答を返して、投票状況の保存を行う状態に遷移する.
+    fn save_ballot_first(
+        &mut self,
+        common: &mut Common<IO>,
+        header: &MessageHeader,
+    ) -> RoleState<IO> {
+        common.rpc_callee(header).reply_busy();
+        RoleState::Follower(Follower::Init(FollowerInit::new(common, None)))
+    }
     fn longest_common_prefix(
         &self,
         common: &Common<IO>,
@@ -1,8 +1,8 @@
 use futures::Future;
 
-use super::super::{Common, NextState, RoleState};
+use super::super::{Common, NextState, Persist, RoleState};
 use super::{Follower, FollowerIdle, FollowerSnapshot};
-use crate::message::{Message, MessageHeader};
+use crate::message::{InstallSnapshotCast, Message, MessageHeader};
 use crate::{Io, Result};
 
 /// 初期化処理を行うフォロワーのサブ状態.
@@ -11,9 +11,11 @@ use crate::{Io, Result};
 ///
 /// - 1. 投票状況を保存
 /// - 2. もし保存処理中に投票先から`RequestVoteCall`を受信したら、保存後にそれに返答(投票)
+/// - 3. もし保存処理中に`InstallSnapshotCast`を受信したら、保存後にそれを処理
 pub struct FollowerInit<IO: Io> {
-    future: IO::SaveBallot,
+    future: Persist<IO, IO::SaveBallot>,
     pending_vote: Option<MessageHeader>,
+    pending_snapshot: Option<Box<InstallSnapshotCast>>,
 }
 impl<IO: Io> FollowerInit<IO> {
     pub fn new(common: &mut Common<IO>, pending_vote: Option<MessageHeader>) -> Self {
@@ -21,8 +23,15 @@ impl<IO: Io> FollowerInit<IO> {
         FollowerInit {
             future,
             pending_vote,
+            pending_snapshot: None,
         }
     }
+
+    /// 投票状況の保存後に処理する`InstallSnapshotCast`を設定する.
+    pub fn with_pending_snapshot(mut self, snapshot: InstallSnapshotCast) -> Self {
+        self.pending_snapshot = Some(Box::new(snapshot));
+        self
+    }
     pub fn handle_message(
         &mut self,
         common: &mut Common<IO>,
@@ -36,6 +45,10 @@ impl<IO: Io> FollowerInit<IO> {
             Message::AppendEntriesCall(m) => {
                 common.rpc_callee(&m.header).reply_busy();
             }
+            Message::InstallSnapshotCast(m) => {
+                // スナップショットには応答が無く、リーダによる再送の契機が無いので、保存後に処理する
+                self.pending_snapshot = Some(Box::new(m));
+            }
             _ => {}
         }
         Ok(None)
@@ -53,7 +66,13 @@ impl<IO: Io> FollowerInit<IO> {
             let next = if common.is_focusing_on_installing_snapshot() {
                 RoleState::Follower(Follower::Snapshot(FollowerSnapshot::new()))
             } else {
-                RoleState::Follower(Follower::Idle(FollowerIdle::new()))
+                let mut idle = FollowerIdle::new();
+                if let Some(snapshot) = self.pending_snapshot.take() {
+                    if let Some(next) = track!(idle.handle_message(common, (*snapshot).into()))? {
+                        return Ok(Some(next));
+                    }
+                }
+                RoleState::Follower(Follower::Idle(idle))
             };
             Ok(Some(next))
         } else {
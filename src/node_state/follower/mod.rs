@@ -34,10 +34,30 @@ pub enum Follower<IO: Io> {
 impl<IO: Io> Follower<IO> {
     pub fn new(common: &mut Common<IO>, pending_vote: Option<MessageHeader>) -> Self {
         common.set_timeout(Role::Follower);
+        if pending_vote.is_none()
+            && common.has_unread_append_entries()
+            && !common.is_focusing_on_installing_snapshot()
+        {
+            // 新しいリーダからのエントリ群の受信を契機とした遷移なので、
+            // 投票状況は、それらのエントリ群と合わせて一度に保存する
+            return Follower::Idle(FollowerIdle::with_unsaved_ballot());
+        }
         let follower = FollowerInit::new(common, pending_vote);
         Follower::Init(follower)
     }
     pub fn handle_timeout(&mut self, common: &mut Common<IO>) -> Result<NextState<IO>> {
+        if common.config().learners().contains(&common.local_node().id) {
+            // 学習者は投票権を持たないので、立候補せずにリーダからの通信を待ち続ける
+            common.set_timeout(Role::Follower);
+            return Ok(None);
+        }
+        let me = common.local_node().id.clone();
+        if common.config().should_defer_election(&me) && !common.is_election_deferred() {
+            // 優先度のより高い投票者に、先に立候補する機会を譲る.
+            // そのノードも立候補しなかった場合には、次のタイムアウトで立候補する.
+            common.defer_election();
+            return Ok(None);
+        }
         Ok(Some(common.transit_to_candidate()))
     }
     pub fn handle_message(
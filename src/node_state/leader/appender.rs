@@ -1,7 +1,9 @@
 use futures::{Async, Future};
 use std::mem;
 
-use super::super::Common;
+use super::super::{Common, Persist};
+use crate::cluster::ClusterConfig;
+use crate::election::Term;
 use crate::log::{LogEntry, LogIndex, LogSuffix};
 use crate::{Io, Result};
 
@@ -11,7 +13,7 @@ use crate::{Io, Result};
 /// ストレージへの追記中に新たな追加要求が発行された場合には、
 /// 新規分はそのバッファに積まれていく.
 pub struct LogAppender<IO: Io> {
-    task: Option<IO::SaveLog>,
+    task: Option<Persist<IO, IO::SaveLog>>,
     in_progress: Option<LogSuffix>,
     pendings: Vec<LogEntry>,
 }
@@ -26,6 +28,24 @@ impl<IO: Io> LogAppender<IO> {
     pub fn is_busy(&self) -> bool {
         self.in_progress.is_some()
     }
+
+    /// 追記処理中ないし追記待ちのエントリ群に、構成変更用のものが含まれている場合には`true`を返す.
+    pub fn has_config_entry(&self) -> bool {
+        self.last_config_entry().is_some()
+    }
+
+    /// 追記処理中ないし追記待ちのエントリ群の中で、最後の構成変更用のエントリの`Term`と構成を返す.
+    pub fn last_config_entry(&self) -> Option<(Term, &ClusterConfig)> {
+        self.in_progress
+            .iter()
+            .flat_map(|s| s.entries.iter())
+            .chain(self.pendings.iter())
+            .rev()
+            .find_map(|e| match *e {
+                LogEntry::Config { term, ref config } => Some((term, config)),
+                _ => None,
+            })
+    }
     pub fn append(&mut self, common: &mut Common<IO>, entries: Vec<LogEntry>) {
         if self.task.is_none() {
             let head = common.log().tail();
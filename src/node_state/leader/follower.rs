@@ -1,5 +1,6 @@
 use futures::{Async, Future};
-use std::collections::BTreeMap;
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet};
 use std::mem;
 use trackable::error::ErrorKindExt;
 
@@ -7,7 +8,7 @@ use super::super::Common;
 use crate::cluster::ClusterConfig;
 use crate::log::{Log, LogIndex};
 use crate::message::{AppendEntriesReply, SequenceNumber};
-use crate::node::NodeId;
+use crate::node::{FollowerStatus, NodeId};
 use crate::{ErrorKind, Io, Result};
 
 /// フォロワーの管理者.
@@ -65,15 +66,15 @@ impl<IO: Io> FollowersManager<IO> {
     ///
     /// "コミット済み"とは「投票権を有するメンバの過半数以上のローカルログに存在する」ということを意味する.
     /// (構成変更中で、新旧構成の両方に投票権が存在する場合には、そのそれぞれの過半数以上)
-    pub fn committed_log_tail(&self) -> LogIndex {
-        self.config.consensus_value(|node_id| {
+    pub fn committed_log_tail(&self) -> Result<LogIndex> {
+        track!(self.config.committable_index(|node_id| {
             let f = &self.followers[node_id];
             if f.synced {
                 f.log_tail
             } else {
                 LogIndex::new(0)
             }
-        })
+        }))
     }
 
     /// ジョイントコミット済みのログ領域の終端を返す.
@@ -81,35 +82,76 @@ impl<IO: Io> FollowersManager<IO> {
     /// 基本的には`committed_log_tail`と同じ動作となるが、
     /// 「構成変更中」かつ「`ClusterState`の値が`CatchUp`」の場合でも、
     /// こちらの関数は常に新旧両方から「過半数以上」を要求する点が異なる.
-    pub fn joint_committed_log_tail(&self) -> LogIndex {
-        self.config.full_consensus_value(|node_id| {
+    pub fn joint_committed_log_tail(&self) -> Result<LogIndex> {
+        track!(self.config.full_consensus_value(|node_id| {
             let f = &self.followers[node_id];
             if f.synced {
                 f.log_tail
             } else {
                 LogIndex::new(0)
             }
-        })
+        }))
     }
 
     pub fn handle_append_entries_reply(
         &mut self,
         common: &Common<IO>,
         reply: &AppendEntriesReply,
-    ) -> bool {
+    ) -> Result<bool> {
         let updated = self.update_follower_state(common, reply);
         if self.latest_hearbeat_ack < reply.header.seq_no {
-            self.latest_hearbeat_ack = self
+            self.latest_hearbeat_ack = track!(self
                 .config
-                .consensus_value(|node_id| self.followers[node_id].last_seq_no);
+                .consensus_value(|node_id| self.followers[node_id].last_seq_no))?;
         }
-        updated
+        Ok(updated)
     }
 
     pub fn set_last_broadcast_seq_no(&mut self, seq_no: SequenceNumber) {
         self.last_broadcast_seq_no = seq_no;
     }
 
+    /// 応答待ちの`AppendEntriesCall`の数が`max_inflight`に達しているフォロワー群を返す.
+    pub fn saturated_followers(&self, max_inflight: usize) -> BTreeSet<NodeId> {
+        self.followers
+            .iter()
+            .filter(|(_, f)| f.inflight.len() >= max_inflight)
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    /// `seq_no`の`AppendEntriesCall`を、`excludes`以外のフォロワーへの応答待ちとして記録する.
+    pub fn add_inflight(
+        &mut self,
+        common: &Common<IO>,
+        seq_no: SequenceNumber,
+        excludes: &BTreeSet<NodeId>,
+    ) {
+        for (id, f) in &mut self.followers {
+            if *id != common.local_node().id && !excludes.contains(id) {
+                f.inflight.insert(seq_no);
+            }
+        }
+    }
+
+    /// ローカルノード以外の各フォロワーの複製状況を、IDの昇順に返す.
+    pub fn status(&self, common: &Common<IO>) -> Vec<FollowerStatus> {
+        let learners = self.config.learners();
+        let leader_tail = common.log().tail().index;
+        self.followers
+            .iter()
+            .filter(|(id, _)| **id != common.local_node().id)
+            .map(|(id, f)| FollowerStatus {
+                id: id.clone(),
+                voter: !learners.contains(id),
+                match_tail: if f.synced { Some(f.log_tail) } else { None },
+                next_index: f.log_tail,
+                last_seq_no: f.last_seq_no,
+                caught_up: f.synced && leader_tail <= f.log_tail,
+            })
+            .collect()
+    }
+
     /// フォロワーのローカルログとの同期処理を実行する.
     pub fn log_sync(&mut self, common: &mut Common<IO>, reply: &AppendEntriesReply) -> Result<()> {
         if reply.busy || self.tasks.contains_key(&reply.header.sender) {
@@ -172,6 +214,13 @@ impl<IO: Io> FollowersManager<IO> {
         if follower.last_seq_no < reply.header.seq_no {
             follower.last_seq_no = reply.header.seq_no;
         }
+
+        // 該当する要求と、それよりも前の要求を応答待ちから外す.
+        // 前の要求は、応答の順番が前後したか、途中で破棄されたかのいずれかなので、
+        // これ以上待つ必要はない(後者の場合は、ハートビートへの応答でウィンドウが解放される).
+        let seq_no = reply.header.seq_no;
+        follower.inflight.retain(|s| *s > seq_no);
+
         match *reply {
             AppendEntriesReply { busy: true, .. } => false,
             AppendEntriesReply { log_tail, .. } if follower.synced => {
@@ -194,7 +243,20 @@ impl<IO: Io> FollowersManager<IO> {
                 if follower.synced {
                     follower.log_tail = log_tail.index;
                 } else {
-                    follower.log_tail = log_tail.index.as_u64().saturating_sub(1).into();
+                    let prev = log_tail.index.as_u64().saturating_sub(1).into();
+                    follower.log_tail = match reply.conflict {
+                        Some(hint) if common.fast_backoff() => {
+                            // フォロワーのログ上の`hint.term`の区間の内、リーダのログにも存在する部分までは一致している.
+                            // リーダのログに`hint.term`が存在しない場合には、その区間全体が不一致となる.
+                            let next = common
+                                .log()
+                                .term_end(hint.term)
+                                .filter(|&i| hint.first_index <= i)
+                                .unwrap_or(hint.first_index);
+                            cmp::min(next, prev)
+                        }
+                        _ => prev,
+                    };
                 }
                 follower.synced
             }
@@ -209,6 +271,9 @@ struct Follower {
     pub log_tail: LogIndex,
     pub last_seq_no: SequenceNumber,
     pub synced: bool,
+
+    // 応答待ちの`AppendEntriesCall`のシーケンス番号群
+    pub inflight: BTreeSet<SequenceNumber>,
 }
 impl Follower {
     pub fn new() -> Self {
@@ -218,6 +283,8 @@ impl Follower {
             log_tail: LogIndex::new(0),
             last_seq_no: SequenceNumber::new(0),
             synced: false,
+
+            inflight: BTreeSet::new(),
         }
     }
 }
@@ -1,9 +1,14 @@
+use std::collections::VecDeque;
+use std::time::Instant;
+
 use self::appender::LogAppender;
 use self::follower::FollowersManager;
 use super::{Common, NextState};
+use crate::cluster::{ChangeId, ClusterMembers, ClusterState};
 use crate::election::Role;
 use crate::log::{LogEntry, LogIndex, LogSuffix, ProposalId};
 use crate::message::{Message, SequenceNumber};
+use crate::node::FollowerStatus;
 use crate::{ErrorKind, Io, Result};
 
 mod appender;
@@ -27,6 +32,15 @@ pub struct Leader<IO: Io> {
     followers: FollowersManager<IO>,
     appender: LogAppender<IO>,
     commit_lower_bound: LogIndex,
+
+    // `check_quorum`用: 最後に確認した、過半数から応答を得たハートビートのシーケンス番号と、
+    // それ以降に過半数からの応答が無いまま経過したタイムアウト回数
+    last_quorum_ack: SequenceNumber,
+    rounds_without_quorum: usize,
+
+    // リーダリース用: 応答待ちのリース確認用のハートビートとその送信時刻、および現在のリースの失効時刻
+    lease_probes: VecDeque<(SequenceNumber, Instant)>,
+    lease_expiry: Option<Instant>,
 }
 impl<IO: Io> Leader<IO> {
     pub fn new(common: &mut Common<IO>) -> Self {
@@ -46,9 +60,26 @@ impl<IO: Io> Leader<IO> {
             followers,
             appender,
             commit_lower_bound: term_start_index,
+            last_quorum_ack: SequenceNumber::new(0),
+            rounds_without_quorum: 0,
+            lease_probes: VecDeque::new(),
+            lease_expiry: None,
         }
     }
     pub fn handle_timeout(&mut self, common: &mut Common<IO>) -> Result<NextState<IO>> {
+        if let Some(max_rounds) = common.check_quorum() {
+            let ack = self.followers.latest_hearbeat_ack();
+            if self.last_quorum_ack < ack {
+                self.last_quorum_ack = ack;
+                self.rounds_without_quorum = 0;
+            } else {
+                self.rounds_without_quorum += 1;
+                if self.rounds_without_quorum > max_rounds {
+                    // 過半数と疎通できていないので、古い状態に基づいて応答してしまうことを防ぐために退く
+                    return Ok(Some(common.step_down()));
+                }
+            }
+        }
         self.broadcast_empty_entries(common);
         Ok(None)
     }
@@ -58,7 +89,7 @@ impl<IO: Io> Leader<IO> {
         message: Message,
     ) -> Result<NextState<IO>> {
         if let Message::AppendEntriesReply(reply) = message {
-            let updated = self.followers.handle_append_entries_reply(&common, &reply);
+            let updated = track!(self.followers.handle_append_entries_reply(&common, &reply))?;
 
             track!(self.followers.log_sync(common, &reply))?;
 
@@ -101,6 +132,11 @@ impl<IO: Io> Leader<IO> {
         }
         track!(self.handle_change_config(common))?;
         track!(self.followers.run_once(common))?;
+        self.lease_expiry = self.lease_expiry(common);
+        let ack = self.followers.latest_hearbeat_ack();
+        while self.lease_probes.front().is_some_and(|&(s, _)| s <= ack) {
+            self.lease_probes.pop_front();
+        }
         Ok(None)
     }
     pub fn propose(&mut self, common: &mut Common<IO>, entry: LogEntry) -> ProposalId {
@@ -108,11 +144,71 @@ impl<IO: Io> Leader<IO> {
         self.appender.append(common, vec![entry]);
         proposal_id
     }
+    pub fn next_proposal_id(&self, common: &Common<IO>) -> ProposalId {
+        let term = common.term();
+        let index = self.appender.unappended_log_tail(common);
+        ProposalId { term, index }
+    }
     pub fn heartbeat_syn(&mut self, common: &mut Common<IO>) -> SequenceNumber {
         let seq_no = common.next_seq_no();
         self.broadcast_empty_entries(common);
         seq_no
     }
+
+    /// リースを更新するためのハートビートを送信する.
+    ///
+    /// 送信したハートビートが過半数から応答を得た時点で、`now`から`Common::lease_duration`の間だけリースが有効となる.
+    pub fn lease_heartbeat_syn(&mut self, common: &mut Common<IO>, now: Instant) -> SequenceNumber {
+        let seq_no = self.heartbeat_syn(common);
+        self.lease_probes.push_back((seq_no, now));
+        seq_no
+    }
+
+    /// 現在のリーダリースの失効時刻を返す.
+    ///
+    /// リースの期間が設定されていない場合や、リース確認用のハートビートがまだ過半数から応答を得ていない場合には`None`が返される.
+    pub fn lease_expiry(&self, common: &Common<IO>) -> Option<Instant> {
+        let duration = common.lease_duration()?;
+        let ack = self.followers.latest_hearbeat_ack();
+        self.lease_probes
+            .iter()
+            .take_while(|&&(seq_no, _)| seq_no <= ack)
+            .map(|&(_, sent_at)| sent_at + duration)
+            .chain(self.lease_expiry)
+            .max()
+    }
+    pub fn is_config_change_in_flight(&self, common: &Common<IO>) -> bool {
+        common.config().is_change_in_flight() || self.appender.has_config_entry()
+    }
+
+    /// 識別子が`id`で新メンバ群が`new`の構成変更が進行中であれば、その提案IDを返す.
+    ///
+    /// 追記待ちの構成変更エントリも対象となる.
+    pub fn find_config_change(
+        &self,
+        common: &Common<IO>,
+        id: ChangeId,
+        new: &ClusterMembers,
+    ) -> Option<ProposalId> {
+        let (term, config) = match self.appender.last_config_entry() {
+            Some(entry) => entry,
+            None => {
+                let config = common.config();
+                let index = config.change_index()?;
+                let term = common.log().get_record(index + 1)?.head.prev_term;
+                (term, config)
+            }
+        };
+        if config.is_same_change(id, new) {
+            let index = config.change_index()?;
+            Some(ProposalId { term, index })
+        } else {
+            None
+        }
+    }
+    pub fn replication_status(&self, common: &Common<IO>) -> Vec<FollowerStatus> {
+        self.followers.status(common)
+    }
     pub fn proposal_queue_len(&self, common: &Common<IO>) -> usize {
         self.appender.unappended_log_tail(common) - common.log().tail().index
     }
@@ -131,33 +227,50 @@ impl<IO: Io> Leader<IO> {
             return Ok(());
         }
 
-        let committed = self.followers.committed_log_tail();
-        if committed < common.log().last_record().head.index {
-            // まだ新構成がコミットされていない可能性がある
+        let committed = track!(self.followers.committed_log_tail())?;
+        let config_tail = common.log().last_record().head.index;
+        if committed < config_tail {
+            // まだ現在の構成がコミットされていない可能性がある
             return Ok(());
         }
 
-        let joint_committed = self.followers.joint_committed_log_tail();
-        if joint_committed == committed {
-            // 新構成のメンバのローカルログが、旧構成のものに追い付いた
-            // => 構成変更の次のフェーズに遷移
+        // NOTE: `committed`と`config_tail`は、いずれも対象エントリの次の位置を指している
+        let joint_committed = track!(self.followers.joint_committed_log_tail())?;
+        let config = common.config();
+        let joint_entry_index = if config.state() == ClusterState::Joint {
+            Some(config_tail - 1)
+        } else {
+            None
+        };
+        let catchup_done = joint_committed == committed;
+        let next = config.on_commit_advanced(committed - 1, joint_entry_index, catchup_done);
+        if next.state() != config.state() {
+            // 構成変更の次のフェーズに遷移
             let term = common.term();
-            let config = common.config().to_next_state();
-            let entry = LogEntry::Config { term, config };
+            let entry = LogEntry::Config { term, config: next };
             self.propose(common, entry);
         }
         Ok(())
     }
-    fn next_proposal_id(&self, common: &Common<IO>) -> ProposalId {
-        let term = common.term();
-        let index = self.appender.unappended_log_tail(common);
-        ProposalId { term, index }
-    }
     fn broadcast_slice(&mut self, common: &mut Common<IO>, slice: LogSuffix) {
-        self.followers
-            .set_last_broadcast_seq_no(common.next_seq_no());
+        let seq_no = common.next_seq_no();
+        self.followers.set_last_broadcast_seq_no(seq_no);
         common.set_timeout(Role::Leader);
-        common.rpc_caller().broadcast_append_entries(slice);
+        match common.max_inflight() {
+            Some(max_inflight) if !slice.entries.is_empty() => {
+                // ウィンドウが埋まっているフォロワーには送信しない.
+                // 未送信分は、応答受信時の`log_sync`で差分として送られる.
+                let saturated = self.followers.saturated_followers(max_inflight);
+                self.followers.add_inflight(common, seq_no, &saturated);
+                common
+                    .rpc_caller()
+                    .broadcast_append_entries_except(slice, &saturated);
+            }
+            _ => {
+                // ハートビートはリーダの維持に必要なので、ウィンドウに関係なく送信する
+                common.rpc_caller().broadcast_append_entries(slice);
+            }
+        }
     }
     fn broadcast_empty_entries(&mut self, common: &mut Common<IO>) {
         let head = common.log().tail();
@@ -166,7 +279,7 @@ impl<IO: Io> Leader<IO> {
         self.broadcast_slice(common, slice);
     }
     fn handle_committed_log(&mut self, common: &mut Common<IO>) -> Result<()> {
-        let committed = self.followers.committed_log_tail();
+        let committed = track!(self.followers.committed_log_tail())?;
         if committed < self.commit_lower_bound {
             // コミット済みのログ領域でも、現在のtermよりも前に追加されたものはまだコミットできない.
             // 詳細は論文の「5.4.2 Committing entries from previous terms」を参照のこと.
@@ -2,8 +2,8 @@ use futures::{Async, Future, Poll};
 
 use super::{Common, NextState};
 use crate::election::Role;
-use crate::log::{Log, LogIndex};
-use crate::{Error, Io, Result};
+use crate::log::{Log, LogIndex, LogSuffix};
+use crate::{Error, ErrorKind, Io, Result};
 
 /// ノード起動時に、前回の状況を復元(ロード)を行う.
 pub struct Loader<IO: Io> {
@@ -47,14 +47,14 @@ impl<IO: Io> Loader<IO> {
                         }
                         Log::Suffix(suffix) => {
                             // 2-2) ログの末尾までを読み込んだ
+                            track!(Self::check_loaded_tail(common, &suffix))?;
+
                             //
                             // NOTE:
-                            // ここで必要なのは「ローカルログの長さ」だけなので、
-                            // 本来は後半部分を全て読み込む必要はない.
-                            //
-                            // もしこれに起因した現実的な性能問題が発生するようであれば、
-                            // 「ローカルログの長さ取得」を行うための専用メソッドを、
-                            // `Io`トレイトに追加しても良いかもしれない.
+                            // ローカルログの長さ自体は`Io::last_log_position`からも取得できるが、
+                            // 後半部分に含まれる構成変更エントリを歴史に記録する必要があるため、
+                            // ここでは全てのエントリを読み込んでいる.
+                            // `last_log_position`は、読み込んだログの検証(`check_loaded_tail`)にのみ使用される.
                             track!(common.handle_log_appended(&suffix))?;
 
                             // FIXME:
@@ -76,6 +76,25 @@ impl<IO: Io> Loader<IO> {
         }
         Ok(None)
     }
+
+    /// 読み込んだログが、永続化済みのはずの末尾まで揃っているかを検証する.
+    ///
+    /// ストレージが途中で切り詰められている場合に、欠けたエントリに気付かずに
+    /// (e.g., コミット済みのエントリを失ったまま)処理を継続してしまうことを防ぐ.
+    fn check_loaded_tail(common: &Common<IO>, suffix: &LogSuffix) -> Result<()> {
+        if let Some((last_index, last_term)) = common.io().last_log_position() {
+            let loaded_tail = suffix.tail();
+            track_assert!(
+                last_index < loaded_tail.index,
+                ErrorKind::InconsistentState,
+                "Loaded log is shorter than persisted one: loaded_tail={:?}, persisted_last=({:?}, {:?})",
+                loaded_tail,
+                last_index,
+                last_term
+            );
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -210,4 +229,32 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn it_fails_if_loaded_log_is_truncated() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let mut io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let term = Term::new(1);
+        io.save_log_suffix(&LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![LogEntry::Noop { term }; 3],
+        });
+
+        // 保存済みの三つのエントリの内、最後の一つが欠けた状態で読み込まれる
+        io.truncate_loaded_log = Some(1);
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id, io, cluster, metrics);
+        let mut loader = Loader::new(&mut common);
+        let e = loop {
+            match loader.run_once(&mut common) {
+                Ok(None) => {}
+                Ok(Some(_)) => panic!("Truncated log must not be accepted"),
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(*e.kind(), ErrorKind::InconsistentState);
+        assert_eq!(common.log().tail().index, LogIndex::new(0));
+        Ok(())
+    }
 }
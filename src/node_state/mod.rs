@@ -1,7 +1,7 @@
 use futures::{Async, Poll, Stream};
 use std::time::Instant;
 
-pub use self::common::Common;
+pub use self::common::{Common, Persist};
 
 use self::candidate::Candidate;
 use self::common::HandleMessageResult;
@@ -120,6 +120,8 @@ impl<IO: Io> Stream for NodeState<IO> {
         let mut did_something = true;
         while did_something {
             did_something = false;
+            self.common.tick();
+
             // イベントチェック
             if let Some(e) = self.common.next_event() {
                 return Ok(Async::Ready(Some(e)));
@@ -177,6 +179,7 @@ impl<IO: Io> Stream for NodeState<IO> {
 }
 
 /// 各役割固有の状態.
+#[allow(clippy::large_enum_variant)]
 pub enum RoleState<IO: Io> {
     /// ノード起動時にストレージから前回の状況を復元するための状態
     Loader(Loader<IO>),
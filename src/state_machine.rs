@@ -0,0 +1,452 @@
+//! コミット済みのログエントリを適用する状態機械.
+use std::cmp;
+use std::collections::BTreeMap;
+
+use crate::log::{LogEntry, LogIndex};
+use crate::{Event, Io};
+
+/// `ReplicatedLog`でコミットされたコマンドを適用する状態機械.
+pub trait StateMachine {
+    /// インデックスが`index`のコマンドを適用する.
+    ///
+    /// `StateMachineDriver`経由で利用する場合には、
+    /// インデックスの昇順に、各コマンドにつき一度だけ呼び出される.
+    fn apply(&mut self, index: LogIndex, command: &[u8]);
+
+    /// インデックスの昇順に並んだコマンド群`entries`を、まとめて適用する.
+    ///
+    /// `StateMachineDriver::handle_events`経由で利用する場合には、
+    /// 一度に渡されるコマンドの数は`StateMachineDriver::with_apply_batch_size`で指定した値以下となる.
+    ///
+    /// デフォルト実装は、各コマンドに対して順に`apply`を呼び出す.
+    fn apply_batch(&mut self, entries: &[(LogIndex, &[u8])]) {
+        for &(index, command) in entries {
+            self.apply(index, command);
+        }
+    }
+
+    /// 状態機械を`snapshot`の状態にリセットする.
+    fn restore(&mut self, snapshot: &[u8]);
+
+    /// `command`を発行したクライアントの要求IDを返す.
+    ///
+    /// `Some`が返された場合には、`StateMachineDriver`は同じクライアントの
+    /// 適用済みの要求以前のシーケンス番号を持つコマンドを、再送されたものとして読み飛ばす.
+    /// そのため、各クライアントは、要求毎に単調増加するシーケンス番号を使用する必要がある.
+    ///
+    /// デフォルト実装は常に`None`を返す(i.e., 重複排除を行わない).
+    fn request_id(&self, command: &[u8]) -> Option<RequestId> {
+        let _ = command;
+        None
+    }
+}
+
+/// クライアントの要求を識別するためのID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RequestId {
+    /// 要求を発行したクライアントのID.
+    pub client_id: u64,
+
+    /// クライアント内での要求のシーケンス番号.
+    pub seq_no: u64,
+}
+
+/// `ReplicatedLog`が発行するイベント群を元に、状態機械にコマンドを適用するドライバ.
+///
+/// ノードの再起動後には、コミット済みのエントリ群がログの先頭から改めて通知されるが、
+/// 適用済みのもの(i.e., インデックスが`last_applied`以下のもの)は読み飛ばされる.
+/// そのため`last_applied`の値は、状態機械と合わせて永続化しておく必要がある.
+///
+/// また`StateMachine::request_id`が要求IDを返すコマンドに関しては、
+/// 同じ要求が(クライアントの再送により)複数回コミットされた場合でも、適用は一度のみとなる.
+/// クライアント毎の適用済みの要求(`applied_requests`)も、状態機械と合わせて永続化しておく必要がある.
+#[derive(Debug)]
+pub struct StateMachineDriver<S> {
+    machine: S,
+    last_applied: Option<LogIndex>,
+    applied_requests: BTreeMap<u64, u64>,
+    apply_batch_size: usize,
+}
+impl<S: StateMachine> StateMachineDriver<S> {
+    /// 新しい`StateMachineDriver`インスタンスを生成する.
+    ///
+    /// `last_applied`には`machine`に適用済みの最後のエントリのインデックスを指定する.
+    pub fn new(machine: S, last_applied: Option<LogIndex>) -> Self {
+        StateMachineDriver {
+            machine,
+            last_applied,
+            applied_requests: BTreeMap::new(),
+            apply_batch_size: 1,
+        }
+    }
+
+    /// `Io::load_applied_index`で復元した適用済みの位置から、適用を再開する`StateMachineDriver`インスタンスを生成する.
+    ///
+    /// 適用済みの位置は、`handle_event`が`true`を返す度に`Io::save_applied_index`で保存しておく必要がある.
+    pub fn resume<IO: Io>(machine: S, io: &IO) -> Self {
+        Self::new(machine, io.load_applied_index())
+    }
+
+    /// クライアント毎の適用済みの要求のシーケンス番号を設定する.
+    pub fn with_applied_requests(mut self, applied_requests: BTreeMap<u64, u64>) -> Self {
+        self.applied_requests = applied_requests;
+        self
+    }
+
+    /// `handle_events`で、一度に状態機械に適用するコマンドの最大数を設定する.
+    ///
+    /// デフォルトは`1`(i.e., 一つずつ適用する).
+    /// `0`が指定された場合には`1`として扱われる.
+    pub fn with_apply_batch_size(mut self, size: usize) -> Self {
+        self.apply_batch_size = cmp::max(size, 1);
+        self
+    }
+
+    /// クライアント毎の適用済みの要求のシーケンス番号を返す.
+    ///
+    /// キーはクライアントのIDで、値はそのクライアントの適用済みの最後の要求のシーケンス番号.
+    pub fn applied_requests(&self) -> &BTreeMap<u64, u64> {
+        &self.applied_requests
+    }
+
+    /// 適用済みの最後のエントリのインデックスを返す.
+    pub fn last_applied(&self) -> Option<LogIndex> {
+        self.last_applied
+    }
+
+    /// 状態機械への参照を返す.
+    pub fn machine(&self) -> &S {
+        &self.machine
+    }
+
+    /// 状態機械への破壊的な参照を返す.
+    pub fn machine_mut(&mut self) -> &mut S {
+        &mut self.machine
+    }
+
+    /// `event`を処理する.
+    ///
+    /// `event`が未適用のエントリのコミットであれば、
+    /// それがコマンドの場合には状態機械に適用し、`true`を返す.
+    /// コマンド以外のエントリや、再送されたコマンドは、適用済みとして扱われる.
+    ///
+    /// `event`が適用済みの位置よりも新しいスナップショットのロードであれば、
+    /// 状態機械をリセットした上で、`true`を返す.
+    pub fn handle_event(&mut self, event: &Event) -> bool {
+        match *event {
+            Event::Committed { index, ref entry } => {
+                if self.last_applied.is_some_and(|i| index <= i) {
+                    return false;
+                }
+                if let LogEntry::Command { ref command, .. } = *entry {
+                    if self.is_new_request(command) {
+                        self.machine.apply(index, command);
+                    }
+                }
+                self.last_applied = Some(index);
+                true
+            }
+            Event::SnapshotLoaded {
+                new_head,
+                ref snapshot,
+            } => {
+                if new_head.index.as_u64() == 0 {
+                    return false;
+                }
+                let last = LogIndex::new(new_head.index.as_u64() - 1);
+                if self.last_applied.is_some_and(|i| last <= i) {
+                    return false;
+                }
+                self.machine.restore(snapshot);
+                self.last_applied = Some(last);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// `events`を順に処理する.
+    ///
+    /// 個々のイベントの扱いは`handle_event`と同様だが、連続してコミットされたコマンド群は、
+    /// `with_apply_batch_size`で指定された数ずつまとめて`StateMachine::apply_batch`で適用される.
+    ///
+    /// いずれかのイベントで適用済みの位置が進んだ場合には`true`を返す.
+    pub fn handle_events<'a, I>(&mut self, events: I) -> bool
+    where
+        I: IntoIterator<Item = &'a Event>,
+    {
+        let mut batch = Vec::with_capacity(self.apply_batch_size);
+        let mut advanced = false;
+        for event in events {
+            if let Event::Committed { index, ref entry } = *event {
+                if self.last_applied.is_some_and(|i| index <= i) {
+                    continue;
+                }
+                if let LogEntry::Command { ref command, .. } = *entry {
+                    if self.is_new_request(command) {
+                        batch.push((index, &command[..]));
+                        if batch.len() == self.apply_batch_size {
+                            self.machine.apply_batch(&batch);
+                            batch.clear();
+                        }
+                    }
+                }
+                self.last_applied = Some(index);
+                advanced = true;
+            } else {
+                // スナップショットのロードよりも前に、保留中のコマンド群を適用しておく
+                if !batch.is_empty() {
+                    self.machine.apply_batch(&batch);
+                    batch.clear();
+                }
+                advanced |= self.handle_event(event);
+            }
+        }
+        if !batch.is_empty() {
+            self.machine.apply_batch(&batch);
+        }
+        advanced
+    }
+
+    fn is_new_request(&mut self, command: &[u8]) -> bool {
+        let id = match self.machine.request_id(command) {
+            Some(id) => id,
+            None => return true,
+        };
+        match self.applied_requests.get(&id.client_id) {
+            Some(&seq_no) if id.seq_no <= seq_no => false,
+            _ => {
+                self.applied_requests.insert(id.client_id, id.seq_no);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use trackable::result::TestResult;
+
+    use super::*;
+    use crate::test_util::tests::TestCluster;
+
+    #[derive(Default)]
+    struct Counter {
+        applied: Vec<LogIndex>,
+        sum: u64,
+    }
+    impl StateMachine for Counter {
+        fn apply(&mut self, index: LogIndex, command: &[u8]) {
+            self.applied.push(index);
+            self.sum += u64::from(command[0]);
+        }
+        fn restore(&mut self, _snapshot: &[u8]) {
+            unreachable!()
+        }
+    }
+
+    fn drive(cluster: &TestCluster, driver: &mut StateMachineDriver<Counter>, handled: &mut usize) {
+        let events = cluster.events("b");
+        for event in &events[*handled..] {
+            driver.handle_event(event);
+        }
+        *handled = events.len();
+        *cluster
+            .node("b")
+            .io()
+            .last_applied
+            .lock()
+            .expect("Never fails") = driver.last_applied();
+    }
+
+    #[test]
+    fn each_command_is_applied_exactly_once_across_restart() -> TestResult {
+        let mut cluster = TestCluster::new(&["a", "b", "c"]);
+        let leader = track!(cluster.elect_leader())?;
+        let mut driver = StateMachineDriver::new(Counter::default(), None);
+        let mut handled = 0;
+
+        for i in 1..=3 {
+            track!(cluster.node_mut(leader.as_str()).propose_command(vec![i]))?;
+        }
+        track!(cluster.run(30))?;
+        drive(&cluster, &mut driver, &mut handled);
+        assert_eq!(driver.machine().sum, 6);
+
+        track!(cluster.restart("b"))?;
+        let last_applied = *cluster
+            .node("b")
+            .io()
+            .last_applied
+            .lock()
+            .expect("Never fails");
+        assert_eq!(last_applied, driver.last_applied());
+
+        // 状態機械と適用済みの位置は永続化されているものとして、ドライバを作り直す
+        let machine = std::mem::take(driver.machine_mut());
+        let mut driver = StateMachineDriver::new(machine, last_applied);
+
+        for i in 4..=5 {
+            track!(cluster.node_mut(leader.as_str()).propose_command(vec![i]))?;
+        }
+        track!(cluster.run(100))?;
+        drive(&cluster, &mut driver, &mut handled);
+
+        // 再起動後には、コミット済みのエントリ群が改めて通知されている
+        let committed = cluster
+            .events("b")
+            .iter()
+            .filter(|e| matches!(e, Event::Committed { index, .. } if index.as_u64() == 1))
+            .count();
+        assert_eq!(committed, 2);
+
+        let indices: Vec<u64> = driver
+            .machine()
+            .applied
+            .iter()
+            .map(|i| i.as_u64())
+            .collect();
+        assert_eq!(indices, [1, 2, 3, 4, 5]);
+        assert_eq!(driver.machine().sum, 15);
+        Ok(())
+    }
+
+    #[test]
+    fn apply_loop_resumes_from_saved_applied_index() -> TestResult {
+        let mut cluster = TestCluster::new(&["a", "b", "c"]);
+        let leader = track!(cluster.elect_leader())?;
+        let mut driver = StateMachineDriver::resume(Counter::default(), cluster.node("b").io());
+        assert_eq!(driver.last_applied(), None);
+
+        let apply = |cluster: &mut TestCluster, driver: &mut StateMachineDriver<Counter>| {
+            let mut applied = None;
+            for event in cluster.events("b") {
+                if driver.handle_event(event) {
+                    applied = driver.last_applied();
+                }
+            }
+            if let Some(index) = applied {
+                unsafe {
+                    cluster.node_mut("b").io_mut().save_applied_index(index);
+                }
+            }
+        };
+
+        for i in 1..=5 {
+            track!(cluster.node_mut(leader.as_str()).propose_command(vec![i]))?;
+        }
+        track!(cluster.run(30))?;
+        apply(&mut cluster, &mut driver);
+        assert_eq!(driver.last_applied(), Some(LogIndex::new(5)));
+
+        track!(cluster.restart("b"))?;
+        let machine = std::mem::take(driver.machine_mut());
+        let mut driver = StateMachineDriver::resume(machine, cluster.node("b").io());
+        assert_eq!(driver.last_applied(), Some(LogIndex::new(5)));
+
+        track!(cluster.node_mut(leader.as_str()).propose_command(vec![6]))?;
+        track!(cluster.run(100))?;
+        apply(&mut cluster, &mut driver);
+
+        let indices: Vec<u64> = driver
+            .machine()
+            .applied
+            .iter()
+            .map(|i| i.as_u64())
+            .collect();
+        assert_eq!(indices, [1, 2, 3, 4, 5, 6]);
+        assert_eq!(driver.machine().sum, 21);
+        Ok(())
+    }
+
+    #[derive(Default)]
+    struct BatchRecorder {
+        batches: Vec<Vec<LogIndex>>,
+    }
+    impl StateMachine for BatchRecorder {
+        fn apply(&mut self, _index: LogIndex, _command: &[u8]) {
+            unreachable!()
+        }
+        fn apply_batch(&mut self, entries: &[(LogIndex, &[u8])]) {
+            self.batches.push(entries.iter().map(|&(i, _)| i).collect());
+        }
+        fn restore(&mut self, _snapshot: &[u8]) {
+            unreachable!()
+        }
+    }
+
+    #[test]
+    fn committed_commands_are_applied_in_batches() {
+        let events = (0..50)
+            .map(|i| Event::Committed {
+                index: LogIndex::new(i),
+                entry: LogEntry::Command {
+                    term: 1.into(),
+                    command: vec![i as u8],
+                },
+            })
+            .collect::<Vec<_>>();
+        let mut driver =
+            StateMachineDriver::new(BatchRecorder::default(), None).with_apply_batch_size(8);
+        assert!(driver.handle_events(&events));
+        assert_eq!(driver.last_applied(), Some(LogIndex::new(49)));
+
+        let sizes = driver
+            .machine()
+            .batches
+            .iter()
+            .map(|b| b.len())
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, [8, 8, 8, 8, 8, 8, 2]);
+        let indices = driver
+            .machine()
+            .batches
+            .iter()
+            .flatten()
+            .map(|i| i.as_u64())
+            .collect::<Vec<_>>();
+        assert_eq!(indices, (0..50).collect::<Vec<_>>());
+
+        // 適用済みのものは読み飛ばされる
+        assert!(!driver.handle_events(&events));
+        assert_eq!(driver.machine().batches.len(), 7);
+    }
+
+    /// コマンドの形式は`[クライアントID, シーケンス番号, 加算値]`.
+    #[derive(Default)]
+    struct DedupCounter(u64);
+    impl StateMachine for DedupCounter {
+        fn apply(&mut self, _index: LogIndex, command: &[u8]) {
+            self.0 += u64::from(command[2]);
+        }
+        fn restore(&mut self, _snapshot: &[u8]) {
+            unreachable!()
+        }
+        fn request_id(&self, command: &[u8]) -> Option<RequestId> {
+            Some(RequestId {
+                client_id: u64::from(command[0]),
+                seq_no: u64::from(command[1]),
+            })
+        }
+    }
+
+    #[test]
+    fn retried_request_is_applied_once() {
+        let mut driver = StateMachineDriver::new(DedupCounter::default(), None);
+        let commands = [[1, 1, 10], [1, 1, 10], [2, 1, 5], [1, 2, 10], [2, 1, 5]];
+        for (i, command) in commands.iter().enumerate() {
+            let event = Event::Committed {
+                index: LogIndex::new(i as u64),
+                entry: LogEntry::Command {
+                    term: 1.into(),
+                    command: command.to_vec(),
+                },
+            };
+            assert!(driver.handle_event(&event));
+        }
+        assert_eq!(driver.machine().0, 25);
+        assert_eq!(driver.last_applied(), Some(LogIndex::new(4)));
+        assert_eq!(driver.applied_requests().get(&1), Some(&2));
+        assert_eq!(driver.applied_requests().get(&2), Some(&1));
+    }
+}
@@ -2,33 +2,60 @@
 #[cfg(test)]
 pub mod tests {
     use fibers::time::timer;
-    use futures::{Async, Future, Poll};
-    use std::collections::{BTreeSet, HashMap};
+    use futures::{Async, Future, Poll, Stream};
+    use prometrics::metrics::MetricBuilder;
+    use std::cmp;
+    use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+    use std::fmt;
+    use std::mem;
+    use std::ops::Bound;
+    use std::sync::mpsc::{self, Receiver, Sender};
     use std::sync::{Arc, Mutex};
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
     use trackable::error::ErrorKindExt;
 
-    use crate::cluster::{ClusterConfig, ClusterMembers};
-    use crate::election::{Ballot, Role};
-    use crate::io::Io;
-    use crate::log::{Log, LogIndex, LogPrefix, LogSuffix};
-    use crate::message::Message;
+    use crate::cluster::{ChangeId, ClusterConfig, ClusterMembers, ClusterState};
+    #[cfg(feature = "compression")]
+    use crate::compression::Compressor;
+    use crate::election::{Ballot, Role, Term};
+    use crate::io::{Io, SaveBallotAndLog};
+    use crate::log::{Log, LogEntry, LogIndex, LogPosition, LogPrefix, LogSuffix};
+    use crate::message::{
+        AppendEntriesCall, AppendEntriesReply, ConflictHint, InstallSnapshotCast, LeaveCast,
+        Message, MessageHeader, RequestVoteCall, RequestVoteReply, SequenceNumber,
+    };
     use crate::node::NodeId;
-    use crate::{Error, ErrorKind, Result};
+    use crate::{Error, ErrorKind, Event, LeaseRead, ReplicatedLog, Result};
 
     type Logs = Arc<Mutex<HashMap<(LogIndex, Option<LogIndex>), Log>>>;
+    type Queues = Arc<Mutex<BTreeMap<NodeId, VecDeque<(u64, Message)>>>>;
+
+    /// `TestIo` がサポートする、保存データの形式のバージョン。
+    pub const STORAGE_VERSION: u8 = 1;
 
     /// `TestIo`を生成する。主にクラスタ構成をするために存在する。
     /// `Log` や `Ballot` の設定は直接 `TestIo` に対して行えばよい。
     #[derive(Debug)]
     pub struct TestIoBuilder {
         members: ClusterMembers,
+        transport: Option<(NodeId, TestNetwork)>,
+        clock: Option<TestClock>,
+        auto_compaction: Option<AutoCompaction>,
+        replay_capacity: usize,
+        durability_delay: u64,
+        persist_timeout: Option<Duration>,
     }
 
     impl TestIoBuilder {
         pub fn new() -> Self {
             Self {
                 members: BTreeSet::new(),
+                transport: None,
+                clock: None,
+                auto_compaction: None,
+                replay_capacity: 0,
+                durability_delay: 0,
+                persist_timeout: None,
             }
         }
 
@@ -37,7 +64,78 @@ pub mod tests {
             self
         }
 
+        /// `node_id` 宛てのメッセージを `network` 経由で送受信するようにする。
+        pub fn network(mut self, node_id: NodeId, network: TestNetwork) -> Self {
+            self.transport = Some((node_id, network));
+            self
+        }
+
+        /// タイムアウトを fibers のタイマーではなく、`clock` で管理するようにする。
+        pub fn clock(mut self, clock: TestClock) -> Self {
+            self.clock = Some(clock);
+            self
+        }
+
+        /// 保存済みのエントリ数が `threshold` を超えた場合に、自動でスナップショットを作成するようにする。
+        ///
+        /// スナップショットはコミット済み領域の終端で作成され、`snapshot_fn` にはその位置が渡される。
+        /// なお、ここでのスナップショットはストレージ上のみのものであり、
+        /// `ReplicatedLog` 側のローカルログの先頭位置は変わらない。
+        pub fn auto_compaction<F>(mut self, threshold: usize, snapshot_fn: F) -> Self
+        where
+            F: Fn(LogPosition) -> LogPrefix + Send + Sync + 'static,
+        {
+            self.auto_compaction = Some(AutoCompaction {
+                threshold,
+                snapshot_fn: Arc::new(snapshot_fn),
+                on_tick: false,
+            });
+            self
+        }
+
+        /// `auto_compaction` と同様だが、スナップショットを作成するかどうかの判定を、
+        /// コミット時ではなく `tick` の呼び出し時に行う。
+        pub fn auto_compaction_on_tick<F>(self, threshold: usize, snapshot_fn: F) -> Self
+        where
+            F: Fn(LogPosition) -> LogPrefix + Send + Sync + 'static,
+        {
+            let mut this = self.auto_compaction(threshold, snapshot_fn);
+            if let Some(ref mut compaction) = this.auto_compaction {
+                compaction.on_tick = true;
+            }
+            this
+        }
+
+        /// 送信したメッセージの内、直近の `capacity` 個を保持するようにする。
+        ///
+        /// 保持されたメッセージは、`TestIo::attach_observer` で後から接続した観測者に再送される。
+        pub fn replay_buffer(mut self, capacity: usize) -> Self {
+            self.replay_capacity = capacity;
+            self
+        }
+
+        /// 保存処理が永続化されるまでに、`ticks` だけの時間(`TestClock` のティック数)を要するようにする。
+        ///
+        /// 永続化が完了するのは、保存処理の `Future` が経過時間を満たした上でポーリングされた時点であり、
+        /// それまでの間にクラッシュした場合(`TestCluster::restart`)には、その書き込みは失われる。
+        /// `clock` が指定されていない場合には無視される(デフォルトは `0`)。
+        pub fn durability_delay(mut self, ticks: u64) -> Self {
+            self.durability_delay = ticks;
+            self
+        }
+
+        /// 保存処理が `timeout` 以内に完了しなかった場合に、タイムアウトさせるようにする。
+        ///
+        /// `Io::create_persist_timeout` も参照のこと(デフォルトではタイムアウトしない)。
+        pub fn persist_timeout(mut self, timeout: Duration) -> Self {
+            self.persist_timeout = Some(timeout);
+            self
+        }
+
         pub fn finish(&self) -> TestIo {
+            if let Some((ref node_id, ref network)) = self.transport {
+                network.connect(node_id);
+            }
             TestIo {
                 leader_timeout: Duration::from_millis(5),
                 follower_timeout: Duration::from_millis(10),
@@ -45,6 +143,38 @@ pub mod tests {
                 cluster: ClusterConfig::new(self.members.clone()),
                 ballots: Arc::new(Mutex::new(Vec::new())),
                 logs: Arc::new(Mutex::new(HashMap::new())),
+                store: Arc::new(Mutex::new(LogStore::default())),
+                disk: Arc::new(Mutex::new(Disk::default())),
+                durability_delay: self.durability_delay,
+                last_applied: Arc::new(Mutex::new(None)),
+                storage_version: STORAGE_VERSION,
+                strict_term_filtering: false,
+                max_term_gap: None,
+                received: Vec::new(),
+                fair_delivery: false,
+                wire_roundtrip: false,
+                #[cfg(feature = "compression")]
+                compressor: None,
+                #[cfg(feature = "compression")]
+                compressed_bytes: 0,
+                truncate_loaded_log: None,
+                recv_counts: BTreeMap::new(),
+                commit_watchers: Arc::new(Mutex::new(Vec::new())),
+                replay: Arc::new(Mutex::new(MessageReplay {
+                    capacity: self.replay_capacity,
+                    history: VecDeque::new(),
+                    observers: Vec::new(),
+                })),
+                auto_compaction: self.auto_compaction.clone(),
+                committed_tail: LogIndex::new(0),
+                ticks: 0,
+                persist_count: 0,
+                commit_latencies: Vec::new(),
+                append_times: BTreeMap::new(),
+                paused: Arc::new(Mutex::new(false)),
+                persist_timeout: self.persist_timeout,
+                transport: self.transport.clone(),
+                clock: self.clock.clone(),
             }
         }
     }
@@ -88,10 +218,80 @@ pub mod tests {
         pub candidate_timeout: Duration,
         /// クラスタ構成。
         pub cluster: ClusterConfig,
-        /// `LoadBallot` でロードされる。
-        pub ballots: Arc<Mutex<Vec<Ballot>>>,
+        /// `LoadBallot` でロードされる。`SaveBallot` で保存された投票状況は末尾に追加される。
+        pub ballots: Arc<Mutex<Vec<Versioned<Ballot>>>>,
         /// `LoadLog` でロードされる。
         pub logs: Logs,
+        /// `SaveLog` で保存されたログ。
+        ///
+        /// `ballots` と同様に、保存要求の時点で反映される(i.e., 永続化前のページキャッシュに相当する)。
+        pub store: Arc<Mutex<LogStore>>,
+        /// 永続化済みの投票状況とログ、および永続化待ちの書き込み群。
+        disk: Arc<Mutex<Disk>>,
+        /// `TestIoBuilder::durability_delay` で指定された、永続化に要する時間。
+        durability_delay: u64,
+        /// 状態機械に適用済みの最後のエントリのインデックス。
+        pub last_applied: Arc<Mutex<Option<LogIndex>>>,
+        /// 以降に保存する投票状況とログの各レコードに記録する、形式のバージョン。
+        ///
+        /// `STORAGE_VERSION` 以外のバージョンで保存されたレコードをロードしようとした場合には、
+        /// `load_ballot` と `load_log` がエラーを返す(i.e., 別バージョンのソフトウェアが保存したデータを模倣する)。
+        pub storage_version: u8,
+        /// `true` の場合は、保存済みの投票状況の `Term` よりも古いメッセージを受信時に破棄する。
+        pub strict_term_filtering: bool,
+        /// `Some(gap)` の場合は、保存済みの投票状況の `Term` よりも `gap` を超えて大きい `Term` のメッセージを、
+        /// 不審なものとして受信時に破棄する。
+        ///
+        /// 長期間分断されていたノードが、極端に大きな `Term` でクラスタを撹乱することを防ぐ。
+        pub max_term_gap: Option<u64>,
+        /// 受信したメッセージ群。
+        pub received: Vec<Message>,
+        /// `true` の場合は、受信可能なメッセージを到着順ではなく、送信元毎に順番に受信する。
+        ///
+        /// 特定の送信元からの大量のメッセージによって、他の送信元のものが後回しにされることを防ぐ。
+        pub fair_delivery: bool,
+        /// `true` の場合は、送信するメッセージを一度バイト列に符号化した上で、復号したものを送信する。
+        ///
+        /// 符号化できない内容を含むメッセージを早期に検出するために使う。
+        pub wire_roundtrip: bool,
+        /// `wire_roundtrip` が `true` の場合に、符号化したメッセージの圧縮・伸張に使われる。
+        #[cfg(feature = "compression")]
+        pub compressor: Option<TestCompressor>,
+        /// `compressor` による圧縮後のメッセージの合計バイト数。
+        #[cfg(feature = "compression")]
+        pub compressed_bytes: u64,
+        /// `Some(n)` の場合は、`load_log` で保存済みのエントリ群を返す際に、末尾の `n` 個を取り除く。
+        ///
+        /// 途中で切り詰められたログファイルからの復旧を模擬するために使う。
+        pub truncate_loaded_log: Option<usize>,
+        /// 送信元毎の受信メッセージ数。
+        recv_counts: BTreeMap<NodeId, u64>,
+        /// コミット済み領域の終端の通知先。
+        commit_watchers: Arc<Mutex<Vec<Sender<LogIndex>>>>,
+        /// 送信したメッセージの履歴と、その観測者。
+        replay: Arc<Mutex<MessageReplay>>,
+        /// 自動スナップショットの設定。
+        auto_compaction: Option<AutoCompaction>,
+        /// `on_commit` で通知された、コミット済み領域の終端。
+        committed_tail: LogIndex,
+        /// `tick` が呼び出された回数。
+        pub ticks: u64,
+        /// 保存処理(`save_ballot_and_log` も一回として数える)が実行された回数。
+        pub persist_count: u64,
+        /// 保存されたエントリ毎の、コミットまでに要した時間(`TestClock` のティック数)。
+        ///
+        /// コミットされた順に記録される。`clock` が指定されていない場合には記録されない。
+        pub commit_latencies: Vec<u64>,
+        /// 未コミットのエントリ毎の、保存時刻。
+        append_times: BTreeMap<LogIndex, u64>,
+        /// `true` の間は、保存処理が完了しない。
+        paused: Arc<Mutex<bool>>,
+        /// 保存処理のタイムアウト時間。`None` の場合はタイムアウトしない。
+        persist_timeout: Option<Duration>,
+        /// メッセージの送受信に使われる。`None` の場合は送受信を行わない。
+        transport: Option<(NodeId, TestNetwork)>,
+        /// タイムアウトの管理に使われる。`None` の場合は fibers のタイマーを使う。
+        clock: Option<TestClock>,
     }
 
     impl TestIo {
@@ -101,36 +301,370 @@ pub mod tests {
                 logs: self.logs.clone(),
             }
         }
+
+        /// `writes` を一つの書き込みとしてディスクに発行し、その永続化の完了を待つためのハンドルを返す。
+        fn write(&self, writes: Vec<DiskWrite>) -> PendingWrite {
+            let id = self
+                .disk
+                .lock()
+                .expect("Never fails")
+                .write(self.storage_version, writes);
+            let ready_at = self
+                .clock
+                .as_ref()
+                .map(|clock| (clock.clone(), clock.now() + self.durability_delay));
+            let pending = PendingWrite {
+                disk: self.disk.clone(),
+                id: Some(id),
+                paused: self.paused.clone(),
+                ready_at,
+            };
+            pending.try_flush();
+            pending
+        }
+
+        /// `writes` をディスクに発行して、即座に永続化する。
+        fn write_now(&self, writes: Vec<DiskWrite>) {
+            let mut disk = self.disk.lock().expect("Never fails");
+            let id = disk.write(self.storage_version, writes);
+            disk.flush(id);
+        }
+
+        /// 書き込みを伴わない(i.e., 即座に完了する)ハンドルを返す。
+        fn no_write(&self) -> PendingWrite {
+            PendingWrite {
+                disk: self.disk.clone(),
+                id: None,
+                paused: self.paused.clone(),
+                ready_at: None,
+            }
+        }
+
+        /// `suffix` と(指定された場合は) `ballot` を、一つの書き込みとして保存する。
+        fn persist_suffix(&mut self, ballot: Option<Ballot>, suffix: &LogSuffix) -> SaveLogImpl {
+            self.persist_count += 1;
+            if let Some(ref clock) = self.clock {
+                let now = clock.now();
+                for index in suffix.head.index.as_u64()..suffix.tail().index.as_u64() {
+                    self.append_times.insert(LogIndex::new(index), now);
+                }
+            }
+            let mut store = self.store.lock().expect("Never fails");
+            if let Err(e) = track!(store.save_suffix(self.storage_version, suffix)) {
+                return SaveLogImpl {
+                    error: Some(e),
+                    pending: self.no_write(),
+                };
+            }
+            let mut writes = Vec::new();
+            if let Some(ballot) = ballot {
+                self.ballots
+                    .lock()
+                    .expect("Never fails")
+                    .push(Versioned::new(self.storage_version, ballot.clone()));
+                writes.push(DiskWrite::Ballot(ballot));
+            }
+            writes.push(DiskWrite::Suffix(suffix.clone()));
+            SaveLogImpl {
+                error: None,
+                pending: self.write(writes),
+            }
+        }
+
+        /// 保存済みのエントリ数が閾値を超えていれば、コミット済み領域の終端でスナップショットを作成する。
+        fn compact_if_needed(&self) {
+            let compaction = match self.auto_compaction {
+                Some(ref compaction) => compaction,
+                None => return,
+            };
+            let mut store = self.store.lock().expect("Never fails");
+            if store.suffix.entries.len() > compaction.threshold {
+                let head = store.suffix.head.index;
+                if let Ok(committed) = store.suffix.slice(head, self.committed_tail) {
+                    let prefix = (compaction.snapshot_fn)(committed.tail());
+                    store.save_prefix(self.storage_version, prefix.clone());
+                    self.write_now(vec![DiskWrite::Prefix(prefix)]);
+                }
+            }
+        }
+
+        /// `compressor` が指定されている場合には、`bytes` を一度圧縮した上で、伸張したものを返す。
+        #[cfg(feature = "compression")]
+        fn compression_roundtrip(&mut self, bytes: Vec<u8>) -> Vec<u8> {
+            match self.compressor {
+                Some(ref compressor) => {
+                    let compressed = compressor.0.compress(&bytes);
+                    self.compressed_bytes += compressed.len() as u64;
+                    compressor
+                        .0
+                        .decompress(&compressed)
+                        .expect("Decompression failed")
+                }
+                None => bytes,
+            }
+        }
+
+        /// ストレージへの保存処理を停止させる。
+        ///
+        /// `resume_io` が呼ばれるまでの間は、投票状況やログの保存処理の完了を示す `Future` が、
+        /// 常に `Async::NotReady` を返すようになる(i.e., fsync が止まった状態を模倣する)。
+        /// なお、停止が解除されても通知は行われないので、定期的にポーリングされることを前提としている。
+        pub fn pause_io(&self) {
+            *self.paused.lock().expect("Never fails") = true;
+        }
+
+        /// `pause_io` で停止させた保存処理を再開させる。
+        pub fn resume_io(&self) {
+            *self.paused.lock().expect("Never fails") = false;
+        }
+
+        /// ゴールデンテスト用に、保存済みの状態を要約して返す。
+        pub fn debug_dump(&self) -> IoState {
+            let store = self.store.lock().expect("Never fails");
+            IoState {
+                ballots: self
+                    .ballots
+                    .lock()
+                    .expect("Never fails")
+                    .iter()
+                    .map(|b| b.value.clone())
+                    .collect(),
+                snapshot_tail: store.prefix.as_ref().map(|p| p.tail),
+                log_head: store.suffix.head,
+                log_terms: store.suffix.entries.iter().map(LogEntry::term).collect(),
+                peers: self.peers(),
+            }
+        }
+
+        /// `commit_latencies` をヒストグラムにして返す。
+        ///
+        /// キーはコミットまでに要したティック数で、値はそのエントリ数。
+        pub fn commit_latency_histogram(&self) -> BTreeMap<u64, usize> {
+            let mut histogram = BTreeMap::new();
+            for &latency in &self.commit_latencies {
+                *histogram.entry(latency).or_default() += 1;
+            }
+            histogram
+        }
+
+        /// 最後に受信したメッセージの送信元を返す。
+        pub fn received_from(&self) -> Option<&NodeId> {
+            self.received.last().map(|m| &m.header().sender)
+        }
+
+        /// 送信元毎の受信メッセージ数を返す。
+        pub fn recv_counts(&self) -> &BTreeMap<NodeId, u64> {
+            &self.recv_counts
+        }
+
+        /// 自ノード宛てにネットワーク上に積まれている、未受信のメッセージ数を返す。
+        ///
+        /// 配送時刻に達していないメッセージも含む。負荷試験で受信の滞留具合を計測するために使う。
+        pub fn channel_depth(&self) -> usize {
+            match self.transport {
+                Some((ref node_id, ref network)) => network.pending_to(node_id),
+                None => 0,
+            }
+        }
+
+        /// 自ノード宛ての未受信のメッセージを全て破棄して、その数を返す。
+        ///
+        /// 負荷試験の各フェーズの間で `channel_depth` をリセットするために使う。
+        pub fn drain_channel(&mut self) -> usize {
+            match self.transport {
+                Some((ref node_id, ref network)) => network.drain(node_id),
+                None => 0,
+            }
+        }
+
+        /// コミット済み領域の終端が進む度に、その値を受信するチャンネルを返す。
+        pub fn commit_watcher(&self) -> Receiver<LogIndex> {
+            let (tx, rx) = mpsc::channel();
+            let mut watchers = self.commit_watchers.lock().expect("Never fails");
+            watchers.push(tx);
+            rx
+        }
+
+        /// 送信したメッセージの観測者として接続する。
+        ///
+        /// 返り値は、`TestIoBuilder::replay_buffer` で保持された直近の送信メッセージ群(送信順)と、
+        /// 以降に送信されるメッセージを受信するチャンネル。
+        pub fn attach_observer(&self) -> (Vec<Message>, Receiver<Message>) {
+            let (tx, rx) = mpsc::channel();
+            let mut replay = self.replay.lock().expect("Never fails");
+            replay.observers.push(tx);
+            (replay.history.iter().cloned().collect(), rx)
+        }
+
+        /// 保存済みのログを全て破棄して、`prefix` のみから成るログに置き換える。
+        ///
+        /// 事前に設定されたログ(`TestIoHandle` 経由でセットされたもの)も破棄されるので、
+        /// 以降の `load_log` は、新しいエントリが保存されるまでは `prefix` のみを返す。
+        pub fn reset_log_to_snapshot(&mut self, prefix: LogPrefix) {
+            let mut logs = self.logs.lock().expect("Never fails");
+            let mut store = self.store.lock().expect("Never fails");
+            logs.clear();
+            store.reset(self.storage_version, prefix.clone());
+            self.write_now(vec![DiskWrite::Reset(prefix)]);
+        }
+
+        /// 永続化済みの最新の投票状況を返す。
+        pub fn durable_ballot(&self) -> Option<Ballot> {
+            self.disk
+                .lock()
+                .expect("Never fails")
+                .ballots
+                .last()
+                .map(|b| b.value.clone())
+        }
+
+        /// 永続化済みのログを返す。
+        ///
+        /// `store` とは異なり、永続化が完了していない書き込みは反映されていない。
+        pub fn durable_log(&self) -> LogStore {
+            self.disk.lock().expect("Never fails").store.clone()
+        }
+
+        /// 保存済みのエントリ群が破損していないかを検査する(`LogStore::verify_integrity` を参照)。
+        pub fn verify_integrity(&self) -> bool {
+            self.store.lock().expect("Never fails").verify_integrity()
+        }
+
+        fn timeout(&self, duration: Duration) -> TestTimeout {
+            if let Some(ref clock) = self.clock {
+                // 論理時計では 1 ミリ秒を 1 ティックとして扱う
+                let expiry_time = clock.now() + duration.as_millis() as u64;
+                TestTimeout::Clock(clock.clone(), expiry_time)
+            } else {
+                TestTimeout::Fibers(timer::timeout(duration))
+            }
+        }
     }
 
     impl Io for TestIo {
-        type SaveBallot = NoopSaveBallot;
+        type SaveBallot = SaveBallotImpl;
         type LoadBallot = LoadBallotImpl;
-        type SaveLog = NoopSaveLog;
+        type SaveLog = SaveLogImpl;
         type LoadLog = LoadLogImpl;
-        type Timeout = FibersTimeout;
+        type Timeout = TestTimeout;
 
         fn try_recv_message(&mut self) -> Result<Option<Message>> {
+            let (node_id, network) = match self.transport {
+                Some((ref node_id, ref network)) => (node_id, network),
+                None => return Ok(None),
+            };
+            loop {
+                let message = if self.fair_delivery {
+                    network.try_recv_fair(node_id, self.received_from())
+                } else {
+                    network.try_recv(node_id)
+                };
+                let message = match message {
+                    Some(message) => message,
+                    None => break,
+                };
+                if self.strict_term_filtering
+                    && self
+                        .durable_ballot_term()
+                        .is_some_and(|term| message.header().term < term)
+                {
+                    // 正しいノードであれば、いずれにせよ拒否するメッセージなので、ここで破棄する
+                    continue;
+                }
+                if let Some(gap) = self.max_term_gap {
+                    let local_term = self.durable_ballot_term().map_or(0, Term::as_u64);
+                    if message.header().term.as_u64() > local_term.saturating_add(gap) {
+                        continue;
+                    }
+                }
+                *self
+                    .recv_counts
+                    .entry(message.header().sender.clone())
+                    .or_default() += 1;
+                self.received.push(message.clone());
+                return Ok(Some(message));
+            }
             Ok(None)
         }
 
-        fn send_message(&mut self, _message: Message) {}
+        fn peers(&self) -> Vec<NodeId> {
+            match self.transport {
+                Some((ref node_id, ref network)) => network
+                    .nodes()
+                    .into_iter()
+                    .filter(|n| n != node_id)
+                    .collect(),
+                None => Vec::new(),
+            }
+        }
+
+        fn connected_peers(&self) -> Option<Vec<NodeId>> {
+            let (node_id, network) = self.transport.as_ref()?;
+            if network.nodes().contains(node_id) {
+                Some(self.peers())
+            } else {
+                Some(Vec::new())
+            }
+        }
+
+        fn send_message(&mut self, message: Message) {
+            let message = if self.wire_roundtrip {
+                let bytes = encode_message(&message);
+                #[cfg(feature = "compression")]
+                let bytes = self.compression_roundtrip(bytes);
+                decode_message(&bytes).expect("Wire roundtrip failed")
+            } else {
+                message
+            };
+            self.replay.lock().expect("Never fails").record(&message);
+            if let Some((_, ref network)) = self.transport {
+                network.send(message);
+            }
+        }
 
-        fn save_ballot(&mut self, _ballot: Ballot) -> Self::SaveBallot {
-            NoopSaveBallot
+        fn save_ballot(&mut self, ballot: Ballot) -> Self::SaveBallot {
+            self.persist_count += 1;
+            let mut ballots = self.ballots.lock().expect("Never fails");
+            ballots.push(Versioned::new(self.storage_version, ballot.clone()));
+            SaveBallotImpl(self.write(vec![DiskWrite::Ballot(ballot)]))
         }
 
         fn load_ballot(&mut self) -> Self::LoadBallot {
             let mut ballots = self.ballots.lock().expect("Never fails");
-            LoadBallotImpl(ballots.pop())
+            if let Some(b) = ballots.last() {
+                if let Err(e) = track!(check_storage_version(b.version)) {
+                    return LoadBallotImpl(Err(e));
+                }
+            }
+            LoadBallotImpl(Ok(ballots.pop().map(|b| b.value)))
         }
 
-        fn save_log_prefix(&mut self, _prefix: LogPrefix) -> Self::SaveLog {
-            NoopSaveLog
+        fn durable_ballot_term(&self) -> Option<Term> {
+            let disk = self.disk.lock().expect("Never fails");
+            disk.ballots.last().map(|b| b.value.term)
         }
 
-        fn save_log_suffix(&mut self, _suffix: &LogSuffix) -> Self::SaveLog {
-            NoopSaveLog
+        fn save_log_prefix(&mut self, prefix: LogPrefix) -> Self::SaveLog {
+            self.persist_count += 1;
+            let mut store = self.store.lock().expect("Never fails");
+            store.save_prefix(self.storage_version, prefix.clone());
+            SaveLogImpl {
+                error: None,
+                pending: self.write(vec![DiskWrite::Prefix(prefix)]),
+            }
+        }
+
+        fn save_log_suffix(&mut self, suffix: &LogSuffix) -> Self::SaveLog {
+            self.persist_suffix(None, suffix)
+        }
+
+        fn save_ballot_and_log(
+            &mut self,
+            ballot: Option<Ballot>,
+            suffix: &LogSuffix,
+        ) -> SaveBallotAndLog<Self::SaveBallot, Self::SaveLog> {
+            // 投票状況とログは、一度の保存処理として扱う
+            SaveBallotAndLog::new(None, self.persist_suffix(ballot, suffix))
         }
 
         fn load_log(&mut self, start: LogIndex, end: Option<LogIndex>) -> Self::LoadLog {
@@ -141,61 +675,481 @@ pub mod tests {
                         return LoadLogImpl {
                             prefix: Some(prefix),
                             suffix: None,
+                            error: None,
                         };
                     }
                     Log::Suffix(suffix) => {
                         return LoadLogImpl {
                             prefix: None,
                             suffix: Some(suffix),
+                            error: None,
                         };
                     }
                 }
             }
-            LoadLogImpl {
-                prefix: None,
-                suffix: Some(LogSuffix::default()),
+            let store = self.store.lock().expect("Never fails");
+            let mut loaded = store.load(start, end);
+            if let (Some(n), Some(suffix)) = (self.truncate_loaded_log, loaded.suffix.as_mut()) {
+                let len = suffix.entries.len();
+                suffix.entries.truncate(len.saturating_sub(n));
+            }
+            loaded
+        }
+
+        fn last_log_position(&self) -> Option<(LogIndex, Term)> {
+            let store = self.store.lock().expect("Never fails");
+            store.last_position()
+        }
+
+        fn save_applied_index(&mut self, index: LogIndex) {
+            *self.last_applied.lock().expect("Never fails") = Some(index);
+        }
+
+        fn load_applied_index(&self) -> Option<LogIndex> {
+            *self.last_applied.lock().expect("Never fails")
+        }
+
+        fn load_entry_term(&self, index: LogIndex) -> Result<Option<Term>> {
+            let store = self.store.lock().expect("Never fails");
+            Ok(store.entry_term(index))
+        }
+
+        fn on_commit(&mut self, committed_tail: LogIndex) {
+            let mut watchers = self.commit_watchers.lock().expect("Never fails");
+            watchers.retain(|tx| tx.send(committed_tail).is_ok());
+
+            self.committed_tail = committed_tail;
+            if let Some(ref clock) = self.clock {
+                let uncommitted = self.append_times.split_off(&committed_tail);
+                let committed = mem::replace(&mut self.append_times, uncommitted);
+                let now = clock.now();
+                self.commit_latencies
+                    .extend(committed.values().map(|&t| now - t));
+            }
+            if self.auto_compaction.as_ref().is_some_and(|c| !c.on_tick) {
+                self.compact_if_needed();
+            }
+        }
+
+        fn now(&self) -> Instant {
+            self.clock
+                .as_ref()
+                .map_or_else(Instant::now, TestClock::instant)
+        }
+
+        fn tick(&mut self) {
+            self.ticks += 1;
+            if self.auto_compaction.as_ref().is_some_and(|c| c.on_tick) {
+                self.compact_if_needed();
             }
         }
 
         fn create_timeout(&mut self, role: Role) -> Self::Timeout {
             match role {
-                Role::Leader => FibersTimeout(timer::timeout(self.leader_timeout)),
-                Role::Follower => FibersTimeout(timer::timeout(self.follower_timeout)),
-                Role::Candidate => FibersTimeout(timer::timeout(self.candidate_timeout)),
+                Role::Leader => self.timeout(self.leader_timeout),
+                Role::Follower => self.timeout(self.follower_timeout),
+                Role::Candidate => self.timeout(self.candidate_timeout),
+            }
+        }
+
+        fn create_persist_timeout(&mut self) -> Option<Self::Timeout> {
+            self.persist_timeout.map(|d| self.timeout(d))
+        }
+    }
+
+    /// `TestIo::debug_dump` が返す、`TestIo` の状態の要約。
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct IoState {
+        /// 保存された投票状況の履歴。
+        pub ballots: Vec<Ballot>,
+        /// 保存済みのスナップショットの終端。
+        pub snapshot_tail: Option<LogPosition>,
+        /// 保存済みのエントリ群の先頭位置。
+        pub log_head: LogPosition,
+        /// 保存済みのエントリ群の `Term` の列。
+        pub log_terms: Vec<Term>,
+        /// 送信先となり得るノード群。
+        pub peers: Vec<NodeId>,
+    }
+
+    /// 形式のバージョンと共に保存されたレコード。
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Versioned<T> {
+        /// 保存時の形式のバージョン。
+        pub version: u8,
+        pub value: T,
+    }
+
+    impl<T> Versioned<T> {
+        pub fn new(version: u8, value: T) -> Self {
+            Versioned { version, value }
+        }
+    }
+
+    /// `version` が `TestIo` のサポートする形式のバージョンであるかを検査する。
+    fn check_storage_version(version: u8) -> Result<()> {
+        track_assert_eq!(
+            version,
+            STORAGE_VERSION,
+            ErrorKind::Other,
+            "Unsupported storage version: {} (supported: {})",
+            version,
+            STORAGE_VERSION
+        );
+        Ok(())
+    }
+
+    /// `TestIo` に保存されたログ。
+    ///
+    /// スナップショットと、それ以降のエントリ群をメモリ上に保持する。
+    #[derive(Debug, Default, Clone)]
+    pub struct LogStore {
+        /// `save_log_prefix` で保存されたスナップショット。
+        pub prefix: Option<LogPrefix>,
+        /// `save_log_suffix` で保存されたエントリ群。
+        pub suffix: LogSuffix,
+        /// `prefix` の保存時の形式のバージョン。
+        prefix_version: u8,
+        /// `suffix` の各エントリの保存時のヘッダ。
+        headers: Vec<EntryHeader>,
+    }
+
+    /// `LogStore` に保存されたエントリ毎のヘッダ。
+    #[derive(Debug, Clone, Copy)]
+    struct EntryHeader {
+        version: u8,
+        checksum: u64,
+    }
+
+    impl LogStore {
+        /// 最後のエントリのインデックスと `Term` を返す。ログが空の場合は `None` を返す。
+        pub fn last_position(&self) -> Option<(LogIndex, Term)> {
+            let tail = self.suffix.tail();
+            if tail.index.as_u64() == 0 {
+                None
+            } else {
+                Some((tail.index - 1, tail.prev_term))
+            }
+        }
+
+        /// `index` の位置にあるエントリの `Term` を返す。
+        ///
+        /// スナップショットに含まれる位置の場合は、スナップショットの終端の `Term` を返す。
+        pub fn entry_term(&self, index: LogIndex) -> Option<Term> {
+            if index < self.suffix.head.index {
+                self.prefix
+                    .as_ref()
+                    .filter(|p| index < p.tail.index)
+                    .map(|p| p.tail.prev_term)
+            } else {
+                let offset = index - self.suffix.head.index;
+                self.suffix.entries.get(offset).map(LogEntry::term)
+            }
+        }
+
+        /// 保存済みのエントリ群を、インデックスと組にして先頭から順に走査するイテレータを返す。
+        ///
+        /// `load` とは異なりエントリ群を複製しないので、大きなログを読み取り専用で走査する場合に使う。
+        pub fn iter_entries(&self) -> impl Iterator<Item = (LogIndex, &LogEntry)> {
+            let head = self.suffix.head.index;
+            self.suffix
+                .entries
+                .iter()
+                .enumerate()
+                .map(move |(i, e)| (head + i, e))
+        }
+
+        /// 保存済みのエントリ群が、保存時から(`save_*` 系のメソッド以外によって)変更されていないかを検査する。
+        ///
+        /// ディスク上のチェックサムを模したもので、テストコードによる誤った書き換えを検出するために使う。
+        pub fn verify_integrity(&self) -> bool {
+            self.suffix.entries.len() == self.headers.len()
+                && self
+                    .suffix
+                    .entries
+                    .iter()
+                    .zip(self.headers.iter())
+                    .all(|(e, h)| checksum(e) == h.checksum)
+        }
+
+        fn load(&self, start: LogIndex, end: Option<LogIndex>) -> LoadLogImpl {
+            if start < self.suffix.head.index {
+                let error = self
+                    .prefix
+                    .as_ref()
+                    .and_then(|_| track!(check_storage_version(self.prefix_version)).err());
+                return LoadLogImpl {
+                    prefix: self.prefix.clone(),
+                    suffix: None,
+                    error,
+                };
+            }
+            let end = end.unwrap_or_else(|| self.suffix.tail().index);
+            let suffix = self.suffix.slice(start, end).ok();
+            let error = suffix.as_ref().and_then(|s| {
+                let offset = s.head.index - self.suffix.head.index;
+                self.headers
+                    .iter()
+                    .skip(offset)
+                    .take(s.entries.len())
+                    .find_map(|h| track!(check_storage_version(h.version)).err())
+            });
+            LoadLogImpl {
+                prefix: None,
+                suffix,
+                error,
+            }
+        }
+
+        fn save_prefix(&mut self, version: u8, prefix: LogPrefix) {
+            if self.suffix.head.index < prefix.tail.index {
+                let len = self.suffix.entries.len();
+                if self.suffix.skip_to(prefix.tail.index).is_err() {
+                    // スナップショットがエントリ群を完全に追い越している
+                    self.suffix.head = prefix.tail;
+                    self.suffix.entries.clear();
+                }
+                if prefix.tail.prev_term != self.suffix.head.prev_term {
+                    self.suffix.head.prev_term = prefix.tail.prev_term;
+                    self.suffix.entries.clear();
+                }
+                let skipped = cmp::min(len - self.suffix.entries.len(), self.headers.len());
+                self.headers.drain(..skipped);
+            }
+            self.prefix = Some(prefix);
+            self.prefix_version = version;
+        }
+
+        fn save_suffix(&mut self, version: u8, suffix: &LogSuffix) -> Result<()> {
+            // 既存のエントリ群の末尾よりも後ろから始まる場合は、間に穴が空いてしまうので拒否する
+            track_assert!(
+                suffix.head.index <= self.suffix.tail().index,
+                ErrorKind::InconsistentState,
+                "Log hole: suffix.head={:?}, last_position={:?}",
+                suffix.head,
+                self.last_position()
+            );
+
+            // 既存のエントリ群と重複する部分は上書きし、それ以降の古いエントリは破棄する
+            let skip = if self.suffix.head.index <= suffix.head.index {
+                0
+            } else {
+                self.suffix.head.index - suffix.head.index
+            };
+            let offset = (suffix.head.index + skip) - self.suffix.head.index;
+            self.suffix.entries.truncate(offset);
+            self.suffix
+                .entries
+                .extend(suffix.entries.iter().skip(skip).cloned());
+            self.headers.truncate(offset);
+            self.headers
+                .extend(suffix.entries.iter().skip(skip).map(|e| EntryHeader {
+                    version,
+                    checksum: checksum(e),
+                }));
+            Ok(())
+        }
+
+        /// 保存済みのログを全て破棄して、`prefix` のみから成るログに置き換える。
+        fn reset(&mut self, version: u8, prefix: LogPrefix) {
+            self.suffix = LogSuffix {
+                head: prefix.tail,
+                entries: Vec::new(),
+            };
+            self.headers.clear();
+            self.prefix = Some(prefix);
+            self.prefix_version = version;
+        }
+    }
+
+    /// `TestIo` のディスクへの書き込み。
+    #[derive(Debug)]
+    enum DiskWrite {
+        Ballot(Ballot),
+        Prefix(LogPrefix),
+        Suffix(LogSuffix),
+        Reset(LogPrefix),
+    }
+
+    /// `TestIo` のディスク。
+    ///
+    /// 発行された書き込みは `unflushed` に発行順に積まれ、`flush` が呼ばれるまでは永続化されない。
+    /// 同じ ID を持つ書き込み群は、まとめて永続化される。
+    #[derive(Debug, Default)]
+    struct Disk {
+        /// 永続化済みの投票状況の履歴。
+        ballots: Vec<Versioned<Ballot>>,
+        /// 永続化済みのログ。
+        store: LogStore,
+        /// 永続化待ちの書き込み群と、その ID および形式のバージョン。
+        unflushed: VecDeque<(u64, u8, DiskWrite)>,
+        next_id: u64,
+    }
+
+    impl Disk {
+        /// `writes` を形式のバージョンが `version` の一つの書き込みとして発行し、その ID を返す。
+        fn write(&mut self, version: u8, writes: Vec<DiskWrite>) -> u64 {
+            let id = self.next_id;
+            self.next_id += 1;
+            self.unflushed
+                .extend(writes.into_iter().map(|w| (id, version, w)));
+            id
+        }
+
+        /// ID が `id` 以下の書き込みを、発行順に永続化する。
+        fn flush(&mut self, id: u64) {
+            while self.unflushed.front().is_some_and(|&(i, _, _)| i <= id) {
+                let (_, version, write) = self.unflushed.pop_front().expect("Never fails");
+                match write {
+                    DiskWrite::Ballot(ballot) => self.ballots.push(Versioned::new(version, ballot)),
+                    DiskWrite::Prefix(prefix) => self.store.save_prefix(version, prefix),
+                    DiskWrite::Suffix(suffix) => {
+                        // 発行時に `TestIo::store` 側で検証済みなので、失敗することはない
+                        let _ = self.store.save_suffix(version, &suffix);
+                    }
+                    DiskWrite::Reset(prefix) => self.store.reset(version, prefix),
+                }
+            }
+        }
+    }
+
+    /// 発行済みの書き込みの永続化を待つためのハンドル。
+    #[derive(Debug)]
+    struct PendingWrite {
+        disk: Arc<Mutex<Disk>>,
+        /// 書き込みの ID。`None` の場合は、書き込みを伴わない。
+        id: Option<u64>,
+        paused: Arc<Mutex<bool>>,
+        /// 永続化が完了する時刻と、その判定に使う時計。
+        ready_at: Option<(TestClock, u64)>,
+    }
+
+    impl PendingWrite {
+        /// 永続化が可能な状態であれば、書き込みを永続化して `true` を返す。
+        fn try_flush(&self) -> bool {
+            if *self.paused.lock().expect("Never fails") {
+                return false;
+            }
+            if let Some((ref clock, ready_at)) = self.ready_at {
+                if clock.now() < ready_at {
+                    return false;
+                }
+            }
+            if let Some(id) = self.id {
+                self.disk.lock().expect("Never fails").flush(id);
+            }
+            true
+        }
+    }
+
+    /// `entry` のチェックサム(符号化したバイト列の FNV-1a ハッシュ値)を返す。
+    fn checksum(entry: &LogEntry) -> u64 {
+        let mut buf = Vec::new();
+        put_entry(&mut buf, entry);
+        buf.iter().fold(0xcbf2_9ce4_8422_2325, |h, &b| {
+            (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
+
+    /// `TestIoBuilder::auto_compaction` で指定された自動スナップショットの設定。
+    #[derive(Clone)]
+    struct AutoCompaction {
+        threshold: usize,
+        snapshot_fn: Arc<dyn Fn(LogPosition) -> LogPrefix + Send + Sync>,
+        /// `true` の場合は `on_commit` ではなく `tick` の呼び出し時に判定を行う。
+        on_tick: bool,
+    }
+
+    impl fmt::Debug for AutoCompaction {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.debug_struct("AutoCompaction")
+                .field("threshold", &self.threshold)
+                .field("on_tick", &self.on_tick)
+                .finish()
+        }
+    }
+
+    /// `TestIo::compressor` に指定する `Compressor`。
+    #[cfg(feature = "compression")]
+    #[derive(Clone)]
+    pub struct TestCompressor(pub Arc<dyn Compressor + Send + Sync>);
+
+    #[cfg(feature = "compression")]
+    impl fmt::Debug for TestCompressor {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.debug_struct("TestCompressor").finish()
+        }
+    }
+
+    /// `TestIo` が送信したメッセージの履歴と、その観測者群。
+    #[derive(Debug)]
+    struct MessageReplay {
+        capacity: usize,
+        history: VecDeque<Message>,
+        observers: Vec<Sender<Message>>,
+    }
+
+    impl MessageReplay {
+        fn record(&mut self, message: &Message) {
+            self.observers.retain(|tx| tx.send(message.clone()).is_ok());
+            if self.capacity == 0 {
+                return;
+            }
+            if self.history.len() == self.capacity {
+                self.history.pop_front();
             }
+            self.history.push_back(message.clone());
         }
     }
 
-    /// 現時点では必要ないので何もしない。
+    /// 投票状況の永続化が完了した時点で完了する。
+    ///
+    /// `TestIo::pause_io` で停止されておらず、`TestIoBuilder::durability_delay` の時間が経過していれば、
+    /// ポーリングされた時点で永続化が行われる。
     #[derive(Debug)]
-    pub struct NoopSaveBallot;
-    impl Future for NoopSaveBallot {
+    pub struct SaveBallotImpl(PendingWrite);
+    impl Future for SaveBallotImpl {
         type Item = ();
         type Error = Error;
         fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+            if !self.0.try_flush() {
+                return Ok(Async::NotReady);
+            }
             Ok(Async::Ready(()))
         }
     }
 
     /// 引数で与えられた `Ballot` を返す `LoadBallot` 実装。
     #[derive(Debug)]
-    pub struct LoadBallotImpl(Option<Ballot>);
+    pub struct LoadBallotImpl(Result<Option<Ballot>>);
     impl Future for LoadBallotImpl {
         type Item = Option<Ballot>;
         type Error = Error;
         fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-            Ok(Async::Ready(self.0.clone()))
+            self.0.clone().map(Async::Ready)
         }
     }
 
-    /// 現時点では必要ないので何もしない。
+    /// ログの永続化が完了した時点で、その結果を返す。
+    ///
+    /// 永続化が行われる条件は `SaveBallotImpl` と同様。
     #[derive(Debug)]
-    pub struct NoopSaveLog;
-    impl Future for NoopSaveLog {
+    pub struct SaveLogImpl {
+        error: Option<Error>,
+        pending: PendingWrite,
+    }
+    impl Future for SaveLogImpl {
         type Item = ();
         type Error = Error;
         fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-            Ok(Async::Ready(()))
+            if !self.pending.try_flush() {
+                return Ok(Async::NotReady);
+            }
+            if let Some(e) = self.error.take() {
+                Err(e)
+            } else {
+                Ok(Async::Ready(()))
+            }
         }
     }
 
@@ -204,11 +1158,15 @@ pub mod tests {
     pub struct LoadLogImpl {
         prefix: Option<LogPrefix>,
         suffix: Option<LogSuffix>,
+        error: Option<Error>,
     }
     impl Future for LoadLogImpl {
         type Item = Log;
         type Error = Error;
         fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+            if let Some(e) = self.error.take() {
+                return Err(e);
+            }
             if let Some(prefix) = self.prefix.clone() {
                 return Ok(Async::Ready(Log::Prefix(prefix)));
             }
@@ -222,17 +1180,975 @@ pub mod tests {
         }
     }
 
-    /// fibers を使ったタイムアウトの実装。
+    /// `TestIo` のタイムアウトの実装。
     #[derive(Debug)]
-    pub struct FibersTimeout(timer::Timeout);
-    impl Future for FibersTimeout {
+    pub enum TestTimeout {
+        /// fibers のタイマーを使ったタイムアウト。
+        Fibers(timer::Timeout),
+        /// `TestClock` が指定の時刻に達した時点で満了するタイムアウト。
+        Clock(TestClock, u64),
+    }
+    impl Future for TestTimeout {
         type Item = ();
         type Error = Error;
 
         fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-            self.0
-                .poll()
-                .map_err(|_| ErrorKind::Other.cause("Broken timer").into())
+            match self {
+                TestTimeout::Fibers(timeout) => timeout
+                    .poll()
+                    .map_err(|_| ErrorKind::Other.cause("Broken timer").into()),
+                TestTimeout::Clock(clock, expiry_time) => {
+                    if *expiry_time <= clock.now() {
+                        Ok(Async::Ready(()))
+                    } else {
+                        Ok(Async::NotReady)
+                    }
+                }
+            }
+        }
+    }
+
+    /// `message` が `RequestVoteCall` かどうかを判定する。
+    pub fn is_request_vote(message: &Message) -> bool {
+        matches!(*message, Message::RequestVoteCall(_))
+    }
+
+    /// `message` が `AppendEntriesCall` かどうかを判定する。
+    pub fn is_append_entries(message: &Message) -> bool {
+        matches!(*message, Message::AppendEntriesCall(_))
+    }
+
+    /// `message` のヘッダに含まれる `Term` を返す。
+    pub fn term_of(message: &Message) -> Term {
+        message.header().term
+    }
+
+    /// 二つの `TestIo` に保存されたエントリ群が、Log Matching 特性を満たしていることを確認する。
+    ///
+    /// 両者に共通するインデックスの範囲で、同じ `Term` を持つ最後のエントリを探し、
+    /// それ以前のエントリが全て一致しているかを先頭から順に比較する。
+    /// 一致しないエントリが見つかった場合は、そのインデックスを含むメッセージでパニックする。
+    pub fn assert_logs_match(a: &TestIo, b: &TestIo) {
+        let a = a.store.lock().expect("Never fails");
+        let b = b.store.lock().expect("Never fails");
+        let (a, b) = (&a.suffix, &b.suffix);
+        let start = std::cmp::max(a.head.index, b.head.index);
+        let end = std::cmp::min(a.tail().index, b.tail().index);
+        if end <= start {
+            return;
+        }
+        fn entry(s: &LogSuffix, index: LogIndex) -> &LogEntry {
+            &s.entries[index - s.head.index]
+        }
+        let len = end - start;
+        let matched = (0..len)
+            .rev()
+            .map(|i| start + i)
+            .find(|&i| entry(a, i).term() == entry(b, i).term());
+        if let Some(matched) = matched {
+            for i in (0..=matched - start).map(|i| start + i) {
+                assert!(
+                    entry(a, i) == entry(b, i),
+                    "Logs diverge at index {}: {:?} != {:?}",
+                    i.as_u64(),
+                    entry(a, i),
+                    entry(b, i)
+                );
+            }
+        }
+    }
+
+    /// テスト用の論理時計。
+    ///
+    /// `TestCluster` では 1 ステップ毎に 1 ティック進む。
+    /// 1 ティックは 1 ミリ秒に相当する。
+    #[derive(Debug, Clone)]
+    pub struct TestClock {
+        ticks: Arc<Mutex<u64>>,
+        epoch: Instant,
+    }
+    impl TestClock {
+        /// 現在時刻を返す。
+        pub fn now(&self) -> u64 {
+            *self.ticks.lock().expect("Never fails")
+        }
+
+        /// 現在時刻を、生成時の `Instant` を起点とした `Instant` に変換して返す。
+        pub fn instant(&self) -> Instant {
+            self.epoch + Duration::from_millis(self.now())
+        }
+
+        /// 時刻を `ticks` だけ進める。
+        pub fn advance(&self, ticks: u64) {
+            *self.ticks.lock().expect("Never fails") += ticks;
+        }
+    }
+    impl Default for TestClock {
+        fn default() -> Self {
+            TestClock {
+                ticks: Arc::default(),
+                epoch: Instant::now(),
+            }
+        }
+    }
+
+    /// `TestIo` 同士でメッセージを配送するためのネットワーク。
+    ///
+    /// 送信されたメッセージは、宛先毎のキューに送信順に積まれる。
+    /// 接続されていないノード宛てのメッセージと、`isolate` で孤立させたノードが送信したメッセージは破棄される。
+    #[derive(Debug, Clone)]
+    pub struct TestNetwork {
+        clock: TestClock,
+        queues: Queues,
+        isolated: Arc<Mutex<BTreeSet<NodeId>>>,
+        ack_delays: Arc<Mutex<BTreeMap<NodeId, u64>>>,
+        latencies: Arc<Mutex<BTreeMap<(NodeId, NodeId), u64>>>,
+    }
+    impl TestNetwork {
+        /// `clock` を配送時刻の管理に使う `TestNetwork` インスタンスを生成する。
+        pub fn new(clock: TestClock) -> Self {
+            TestNetwork {
+                clock,
+                queues: Arc::default(),
+                isolated: Arc::default(),
+                ack_delays: Arc::default(),
+                latencies: Arc::default(),
+            }
+        }
+
+        /// `node_id` をネットワークに接続する。
+        ///
+        /// `isolate` で孤立させていた場合には、その送信も再び配送されるようになる。
+        pub fn connect(&self, node_id: &NodeId) {
+            self.isolated.lock().expect("Never fails").remove(node_id);
+            let mut queues = self.queues.lock().expect("Never fails");
+            queues.entry(node_id.clone()).or_default();
+        }
+
+        /// ネットワークに接続しているノード群を、ID 順に返す。
+        pub fn nodes(&self) -> Vec<NodeId> {
+            let queues = self.queues.lock().expect("Never fails");
+            queues.keys().cloned().collect()
+        }
+
+        /// `node_id` をネットワークから切断する。未受信のメッセージは破棄される。
+        pub fn disconnect(&self, node_id: &NodeId) {
+            let mut queues = self.queues.lock().expect("Never fails");
+            queues.remove(node_id);
+        }
+
+        /// `node_id` をネットワークから孤立させる(i.e., ネットワーク分断を模擬する)。
+        ///
+        /// `disconnect` と異なり、`connect` されるまでの間は、`node_id` が送信したメッセージも破棄される。
+        pub fn isolate(&self, node_id: &NodeId) {
+            self.isolated
+                .lock()
+                .expect("Never fails")
+                .insert(node_id.clone());
+            self.disconnect(node_id);
+        }
+
+        /// `node_id` が送信するメッセージ(i.e., フォロワーであればリーダへの応答)の配送を、
+        /// `ticks` だけ遅延させる。
+        ///
+        /// 遅延中のメッセージは、他の送信元からのメッセージの配送を妨げない。
+        pub fn set_ack_delay(&self, node_id: &NodeId, ticks: u64) {
+            let mut delays = self.ack_delays.lock().expect("Never fails");
+            delays.insert(node_id.clone(), ticks);
+        }
+
+        /// `from` から `to` へのメッセージの配送に、`ticks` だけ掛かるようにする。
+        ///
+        /// `set_ack_delay` による遅延とは別に加算される。
+        /// 同じ経路のメッセージは、遅延が変更された場合でも、送信順に配送される。
+        pub fn set_latency(&self, from: &NodeId, to: &NodeId, ticks: u64) {
+            let mut latencies = self.latencies.lock().expect("Never fails");
+            latencies.insert((from.clone(), to.clone()), ticks);
+        }
+
+        /// `node_id` 宛てのメッセージの内、未受信のものの数を返す。
+        pub fn pending_to(&self, node_id: &NodeId) -> usize {
+            let queues = self.queues.lock().expect("Never fails");
+            queues.get(node_id).map_or(0, |queue| queue.len())
+        }
+
+        /// `node_id` 宛ての未受信のメッセージを全て破棄して、その数を返す。
+        pub fn drain(&self, node_id: &NodeId) -> usize {
+            let mut queues = self.queues.lock().expect("Never fails");
+            queues
+                .get_mut(node_id)
+                .map_or(0, |queue| queue.drain(..).count())
+        }
+
+        fn send(&self, message: Message) {
+            let edge = (
+                message.header().sender.clone(),
+                message.header().destination.clone(),
+            );
+            let delay = {
+                let delays = self.ack_delays.lock().expect("Never fails");
+                delays.get(&edge.0).cloned().unwrap_or(0)
+            };
+            let latency = {
+                let latencies = self.latencies.lock().expect("Never fails");
+                latencies.get(&edge).cloned().unwrap_or(0)
+            };
+            let mut arrival_time = self.clock.now() + delay + latency;
+            if self.isolated.lock().expect("Never fails").contains(&edge.0) {
+                return;
+            }
+            let mut queues = self.queues.lock().expect("Never fails");
+            if let Some(queue) = queues.get_mut(&edge.1) {
+                // 同じ経路の先行するメッセージを追い越さないようにする
+                if let Some(&(last, _)) = queue
+                    .iter()
+                    .rev()
+                    .find(|(_, m)| m.header().sender == edge.0)
+                {
+                    arrival_time = cmp::max(arrival_time, last);
+                }
+                queue.push_back((arrival_time, message));
+            }
+        }
+
+        fn try_recv(&self, node_id: &NodeId) -> Option<Message> {
+            let now = self.clock.now();
+            let mut queues = self.queues.lock().expect("Never fails");
+            let queue = queues.get_mut(node_id)?;
+            let i = queue.iter().position(|&(t, _)| t <= now)?;
+            queue.remove(i).map(|(_, message)| message)
+        }
+
+        /// `try_recv` と同様だが、送信元毎に順番に(ID 順で循環して)メッセージを選ぶ。
+        ///
+        /// 配送可能なメッセージの送信元の中から、`last_sender` の次の送信元のものが選ばれる。
+        fn try_recv_fair(&self, node_id: &NodeId, last_sender: Option<&NodeId>) -> Option<Message> {
+            let now = self.clock.now();
+            let mut queues = self.queues.lock().expect("Never fails");
+            let queue = queues.get_mut(node_id)?;
+            let sender = {
+                let senders: BTreeSet<_> = queue
+                    .iter()
+                    .filter(|&&(t, _)| t <= now)
+                    .map(|(_, m)| &m.header().sender)
+                    .collect();
+                let next = last_sender.and_then(|last| {
+                    senders
+                        .range::<&NodeId, _>((Bound::Excluded(last), Bound::Unbounded))
+                        .next()
+                });
+                NodeId::clone(next.or_else(|| senders.iter().next())?)
+            };
+            let i = queue
+                .iter()
+                .position(|(t, m)| *t <= now && m.header().sender == sender)?;
+            queue.remove(i).map(|(_, message)| message)
+        }
+    }
+
+    /// `TestIo` を使った `ReplicatedLog` 群から構成されるテスト用のクラスタ。
+    ///
+    /// 全てのノードは `TestNetwork` と `TestClock` を共有しており、
+    /// `step` を呼び出す度に、時計を 1 ティック進めた上で、各ノードを ID 順にポーリングする。
+    /// 同じ操作を行えば、常に同じ結果が得られる。
+    ///
+    /// 最初の選挙は、ID が最も小さいノードが勝つように、ノード毎のタイムアウトがずらされている。
+    pub struct TestCluster {
+        pub clock: TestClock,
+        pub network: TestNetwork,
+        nodes: BTreeMap<NodeId, ReplicatedLog<TestIo>>,
+        events: BTreeMap<NodeId, Vec<Event>>,
+        watchdog: Option<Watchdog>,
+        lease_duration: Option<Duration>,
+    }
+    impl TestCluster {
+        /// `ids` をメンバとするクラスタを生成する。
+        pub fn new(ids: &[&str]) -> Self {
+            Self::with_durability_delay(ids, 0)
+        }
+
+        /// `ids` をメンバとし、各ノードの保存処理の永続化に `ticks` だけの時間を要するクラスタを生成する。
+        ///
+        /// `TestIoBuilder::durability_delay` も参照のこと。
+        pub fn with_durability_delay(ids: &[&str], ticks: u64) -> Self {
+            Self::with_io_options(ids, |builder| builder.durability_delay(ticks))
+        }
+
+        /// `ids` をメンバとし、各ノードの `TestIoBuilder` に `configure` を適用したクラスタを生成する。
+        ///
+        /// 送受信用のネットワークやクロックは、`configure` の適用前に設定済みである。
+        pub fn with_io_options<F>(ids: &[&str], configure: F) -> Self
+        where
+            F: Fn(TestIoBuilder) -> TestIoBuilder,
+        {
+            let clock = TestClock::default();
+            let network = TestNetwork::new(clock.clone());
+            let members: ClusterMembers = ids.iter().map(|id| NodeId::new(*id)).collect();
+            let mut nodes = BTreeMap::new();
+            let mut events = BTreeMap::new();
+            for (i, node_id) in members.iter().enumerate() {
+                let builder = TestIoBuilder::new()
+                    .network(node_id.clone(), network.clone())
+                    .clock(clock.clone());
+                let mut io = configure(builder).finish();
+                let delay = Duration::from_millis(10 * i as u64);
+                io.follower_timeout += delay;
+                io.candidate_timeout += delay;
+                let node = ReplicatedLog::new(
+                    node_id.clone(),
+                    members.clone(),
+                    io,
+                    &MetricBuilder::without_registry(),
+                )
+                .expect("Never fails");
+                nodes.insert(node_id.clone(), node);
+                events.insert(node_id.clone(), Vec::new());
+            }
+            TestCluster {
+                clock,
+                network,
+                nodes,
+                events,
+                watchdog: None,
+                lease_duration: None,
+            }
+        }
+
+        /// 指定されたノードを返す。
+        pub fn node(&self, node_id: &str) -> &ReplicatedLog<TestIo> {
+            &self.nodes[&NodeId::new(node_id)]
+        }
+
+        /// 指定されたノードを返す。
+        pub fn node_mut(&mut self, node_id: &str) -> &mut ReplicatedLog<TestIo> {
+            self.nodes
+                .get_mut(&NodeId::new(node_id))
+                .expect("Unknown node")
+        }
+
+        /// 指定されたノードで、これまでに発生したイベント群を返す。
+        pub fn events(&self, node_id: &str) -> &[Event] {
+            &self.events[&NodeId::new(node_id)]
+        }
+
+        /// 現在のリーダを返す。
+        ///
+        /// リーダが存在しない場合や、複数のノードがリーダを自称している場合は `None` を返す。
+        pub fn leader(&self) -> Option<NodeId> {
+            let mut leaders = self
+                .nodes
+                .values()
+                .filter(|n| n.local_node().role == Role::Leader)
+                .map(|n| n.local_node().id.clone());
+            match (leaders.next(), leaders.next()) {
+                (Some(leader), None) => Some(leader),
+                _ => None,
+            }
+        }
+
+        /// 時計を 1 ティック進めて、全てのノードをポーリングする。
+        ///
+        /// `set_watchdog` で設定された回数を超えて、進捗の無いステップが続いた場合には、
+        /// 各ノードの状態(`diagnostics` を参照)を含むメッセージと共に panic する。
+        pub fn step(&mut self) -> Result<()> {
+            self.clock.advance(1);
+            for (node_id, node) in &mut self.nodes {
+                let events = self.events.get_mut(node_id).expect("Never fails");
+                while let Async::Ready(Some(event)) = track!(node.poll())? {
+                    events.push(event);
+                }
+            }
+            self.check_progress();
+            Ok(())
+        }
+
+        /// 進捗の無いステップが `max_stalled_steps` 回を超えて続いた場合に、`step` が panic するようにする。
+        ///
+        /// 進捗とは、いずれかのノードでのコミット済み領域の前進・役割の変化・メッセージの受信のこと。
+        /// `None` が指定された場合には、監視を行わない(デフォルト)。
+        pub fn set_watchdog(&mut self, max_stalled_steps: Option<usize>) {
+            self.watchdog = max_stalled_steps.map(|max_stalled_steps| Watchdog {
+                max_stalled_steps,
+                stalled_steps: 0,
+                last_progress: self.progress(),
+            });
+        }
+
+        /// 各ノードの役割・`Term`・コミット済み領域の終端・未受信のメッセージ数を、一ノード一行で返す。
+        pub fn diagnostics(&self) -> String {
+            self.nodes
+                .iter()
+                .map(|(id, node)| {
+                    let local = node.local_node();
+                    format!(
+                        "node={} role={:?} term={} committed={} queued={}\n",
+                        id.as_str(),
+                        local.role,
+                        local.ballot.term.as_u64(),
+                        node.local_history().committed_tail().index.as_u64(),
+                        self.network.pending_to(id)
+                    )
+                })
+                .collect()
+        }
+
+        fn progress(&self) -> Vec<(Role, LogIndex, u64)> {
+            self.nodes
+                .values()
+                .map(|node| {
+                    (
+                        node.local_node().role,
+                        node.local_history().committed_tail().index,
+                        node.io().recv_counts().values().sum(),
+                    )
+                })
+                .collect()
+        }
+
+        fn check_progress(&mut self) {
+            let progress = match self.watchdog {
+                Some(_) => self.progress(),
+                None => return,
+            };
+            let watchdog = self.watchdog.as_mut().expect("Never fails");
+            if progress != watchdog.last_progress {
+                watchdog.last_progress = progress;
+                watchdog.stalled_steps = 0;
+                return;
+            }
+            watchdog.stalled_steps += 1;
+            if watchdog.stalled_steps > watchdog.max_stalled_steps {
+                let stalled_steps = watchdog.stalled_steps;
+                panic!(
+                    "Watchdog: no progress for {} steps\n{}",
+                    stalled_steps,
+                    self.diagnostics()
+                );
+            }
+        }
+
+        /// 各ノードのリーダリースの期間を、`ticks` に設定する。
+        ///
+        /// 設定は、以後に `restart` されたノードにも引き継がれる。
+        /// デフォルトではリースは付与されず、全ての読み込みで過半数の承認が必要となる。
+        pub fn set_lease_duration(&mut self, ticks: u64) {
+            let duration = Duration::from_millis(ticks);
+            self.lease_duration = Some(duration);
+            for node in self.nodes.values_mut() {
+                node.set_lease_duration(Some(duration));
+            }
+        }
+
+        /// `leader` が、現在の時刻において有効なリーダリースを保持しているかどうかを判定する。
+        ///
+        /// `ReplicatedLog::lease_valid` も参照のこと。
+        pub fn lease_valid(&self, leader: &str) -> bool {
+            let now = self.instant();
+            self.nodes
+                .get(&NodeId::new(leader))
+                .is_some_and(|n| n.lease_valid(now))
+        }
+
+        /// 時計を、現在のリーダが保持するリースが失効する時刻まで進める。
+        ///
+        /// ノードのポーリングは行わないので、時計以外の状態は変化しない。
+        /// リースが存在しないか、既に失効している場合には何もしない。
+        pub fn advance_past_lease(&mut self) {
+            let expiry = self.nodes.values().filter_map(|n| n.lease_expiry()).max();
+            if let Some(expiry) = expiry {
+                let now = self.instant();
+                if now < expiry {
+                    // 時刻は全てミリ秒単位で進むので、端数は生じない
+                    self.clock.advance((expiry - now).as_millis() as u64);
+                }
+            }
+        }
+
+        /// `leader` 上で読み込み専用のクエリを処理する。
+        ///
+        /// `ReplicatedLog::lease_read` が `LeaseRead::Confirm` を返した場合には、
+        /// そのハートビートが過半数に承認されるまでクラスタを動作させる。
+        ///
+        /// `leader` がリーダではない場合には `ErrorKind::NotLeader` を理由としたエラーが、
+        /// 承認が得られなかった場合には `ErrorKind::Other` を理由としたエラーが返される。
+        pub fn lease_read(&mut self, leader: &str) -> Result<LeaseRead> {
+            let now = self.instant();
+            let read = track!(self.node_mut(leader).lease_read(now))?;
+            if let LeaseRead::Confirm(seq_no) = read {
+                let confirmed = track!(
+                    self.run_until(1000, |c| c.node(leader).last_heartbeat_ack() >= seq_no)
+                )?;
+                track_assert!(
+                    confirmed,
+                    ErrorKind::Other,
+                    "Heartbeat {:?} was not acknowledged by a quorum",
+                    seq_no
+                );
+            }
+            Ok(read)
+        }
+
+        /// `TestClock` の現在時刻を、`Instant` に変換して返す。
+        fn instant(&self) -> Instant {
+            self.clock.instant()
+        }
+
+        /// `step` を `steps` 回繰り返す。
+        pub fn run(&mut self, steps: usize) -> Result<()> {
+            for _ in 0..steps {
+                track!(self.step())?;
+            }
+            Ok(())
+        }
+
+        /// `f` が `true` を返すまで、最大 `max_steps` 回 `step` を繰り返す。
+        ///
+        /// `f` が `true` を返した場合には `true` が返される。
+        pub fn run_until<F>(&mut self, max_steps: usize, f: F) -> Result<bool>
+        where
+            F: Fn(&Self) -> bool,
+        {
+            for _ in 0..max_steps {
+                if f(self) {
+                    return Ok(true);
+                }
+                track!(self.step())?;
+            }
+            Ok(f(self))
+        }
+
+        /// リーダが選出されるまでクラスタを動作させ、そのリーダを返す。
+        pub fn elect_leader(&mut self) -> Result<NodeId> {
+            let elected = track!(self.run_until(1000, |c| c.leader().is_some()))?;
+            track_assert!(elected, ErrorKind::Other, "No leader was elected");
+            Ok(self.leader().expect("Never fails"))
+        }
+
+        /// 指定されたノードが、票を割ることなく次の選挙に勝つように、クラスタを動作させる。
+        ///
+        /// 選挙の間は、他のノードのタイムアウトを延ばして対抗馬が現れないようにした上で、
+        /// 指定されたノードに立候補させる。
+        /// 他の全てのノードが、フォロワーとしてそのノードをリーダと認めた時点で返る(タイムアウトは元に戻される)。
+        ///
+        /// 指定されたノードのログが、過半数のノードのログ以上に新しくない場合には、選挙に勝つことはできない。
+        pub fn force_leader(&mut self, node_id: &str) -> Result<()> {
+            let target = NodeId::new(node_id);
+            track_assert!(
+                self.nodes.contains_key(&target),
+                ErrorKind::InvalidInput,
+                "Unknown node: {:?}",
+                target
+            );
+
+            let hold = Duration::from_secs(3600);
+            let mut saved = BTreeMap::new();
+            for (id, node) in self.nodes.iter_mut().filter(|(id, _)| **id != target) {
+                let io = unsafe { node.io_mut() };
+                saved.insert(id.clone(), (io.follower_timeout, io.candidate_timeout));
+                io.follower_timeout += hold;
+                io.candidate_timeout += hold;
+            }
+
+            let mut elected = false;
+            for _ in 0..1000 {
+                if self.is_acknowledged_leader(&target) {
+                    elected = true;
+                    break;
+                }
+                let node = self.nodes.get_mut(&target).expect("Never fails");
+                if node.local_node().role == Role::Follower {
+                    node.start_election();
+                }
+                track!(self.step())?;
+            }
+
+            for (id, (follower_timeout, candidate_timeout)) in saved {
+                if let Some(node) = self.nodes.get_mut(&id) {
+                    let io = unsafe { node.io_mut() };
+                    io.follower_timeout = follower_timeout;
+                    io.candidate_timeout = candidate_timeout;
+                }
+            }
+            track_assert!(
+                elected,
+                ErrorKind::Other,
+                "{:?} could not win the election",
+                target
+            );
+            Ok(())
+        }
+
+        fn is_acknowledged_leader(&self, leader: &NodeId) -> bool {
+            let term = self.nodes[leader].local_node().ballot.term;
+            self.leader().as_ref() == Some(leader)
+                && self.nodes.values().all(|n| {
+                    let local = n.local_node();
+                    local.id == *leader
+                        || (local.role == Role::Follower
+                            && local.ballot.term == term
+                            && local.ballot.voted_for == *leader)
+                })
+        }
+
+        /// 指定されたノードを停止する。
+        ///
+        /// 停止前に、他のノードに停止通知を送信する。
+        pub fn shutdown(&mut self, node_id: &str) {
+            let node_id = NodeId::new(node_id);
+            if let Some(mut node) = self.nodes.remove(&node_id) {
+                node.leave();
+            }
+            self.network.disconnect(&node_id);
+        }
+
+        /// 指定されたノードをクラッシュさせた上で、再起動する。
+        ///
+        /// 再起動後のノードに引き継がれるのは、`TestIo` に永続化済みの投票状況とログ、適用済みの位置のみであり、
+        /// メモリ上の状態や、ノード宛ての未受信のメッセージ、永続化が完了していない書き込みは失われる。
+        pub fn restart(&mut self, node_id: &str) -> Result<()> {
+            let node_id = NodeId::new(node_id);
+            let old = track!(self.nodes.remove(&node_id).ok_or_else(
+                || ErrorKind::InvalidInput.cause(format!("Unknown node: {:?}", node_id))
+            ))?;
+            self.network.disconnect(&node_id);
+
+            let mut io = TestIoBuilder::new()
+                .network(node_id.clone(), self.network.clone())
+                .clock(self.clock.clone())
+                .durability_delay(old.io().durability_delay)
+                .finish();
+            io.persist_timeout = old.io().persist_timeout;
+            io.follower_timeout = old.io().follower_timeout;
+            io.candidate_timeout = old.io().candidate_timeout;
+            {
+                let mut disk = old.io().disk.lock().expect("Never fails");
+                disk.unflushed.clear();
+                *io.ballots.lock().expect("Never fails") = disk.ballots.clone();
+                *io.store.lock().expect("Never fails") = disk.store.clone();
+            }
+            io.disk = old.io().disk.clone();
+            io.last_applied = old.io().last_applied.clone();
+            io.storage_version = old.io().storage_version;
+            let members = old.cluster_config().primary_members().clone();
+            let mut node = track!(ReplicatedLog::new(
+                node_id.clone(),
+                members,
+                io,
+                &MetricBuilder::without_registry(),
+            ))?;
+            node.set_lease_duration(self.lease_duration);
+            self.nodes.insert(node_id, node);
+            Ok(())
+        }
+    }
+
+    /// `TestCluster::set_watchdog` で設定された、進捗の監視状況。
+    struct Watchdog {
+        max_stalled_steps: usize,
+        stalled_steps: usize,
+        last_progress: Vec<(Role, LogIndex, u64)>,
+    }
+
+    /// `message` をバイト列に符号化する。
+    ///
+    /// `TestIo` の `wire_roundtrip` で使われるテスト用の形式であり、互換性は考慮されていない。
+    pub fn encode_message(message: &Message) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let (tag, header) = match message {
+            Message::RequestVoteCall(m) => (0, &m.header),
+            Message::RequestVoteReply(m) => (1, &m.header),
+            Message::AppendEntriesCall(m) => (2, &m.header),
+            Message::AppendEntriesReply(m) => (3, &m.header),
+            Message::InstallSnapshotCast(m) => (4, &m.header),
+            Message::LeaveCast(m) => (5, &m.header),
+        };
+        buf.push(tag);
+        put_bytes(&mut buf, header.sender.as_str().as_bytes());
+        put_bytes(&mut buf, header.destination.as_str().as_bytes());
+        put_u64(&mut buf, header.seq_no.as_u64());
+        put_u64(&mut buf, header.term.as_u64());
+        match message {
+            Message::RequestVoteCall(m) => put_position(&mut buf, m.log_tail),
+            Message::RequestVoteReply(m) => buf.push(m.voted as u8),
+            Message::AppendEntriesCall(m) => {
+                put_u64(&mut buf, m.committed_log_tail.as_u64());
+                put_position(&mut buf, m.suffix.head);
+                put_u64(&mut buf, m.suffix.entries.len() as u64);
+                for entry in &m.suffix.entries {
+                    put_entry(&mut buf, entry);
+                }
+            }
+            Message::AppendEntriesReply(m) => {
+                put_position(&mut buf, m.log_tail);
+                buf.push(m.busy as u8);
+                match m.conflict {
+                    None => buf.push(0),
+                    Some(hint) => {
+                        buf.push(1);
+                        put_u64(&mut buf, hint.term.as_u64());
+                        put_u64(&mut buf, hint.first_index.as_u64());
+                    }
+                }
+            }
+            Message::InstallSnapshotCast(m) => {
+                put_position(&mut buf, m.prefix.tail);
+                put_config(&mut buf, &m.prefix.config);
+                put_bytes(&mut buf, &m.prefix.snapshot);
+            }
+            Message::LeaveCast(_) => {}
+        }
+        buf
+    }
+
+    /// `encode_message` で符号化されたバイト列から、メッセージを復号する。
+    pub fn decode_message(bytes: &[u8]) -> Result<Message> {
+        let mut r = WireReader(bytes);
+        let tag = track!(r.u8())?;
+        let header = MessageHeader {
+            sender: track!(r.node_id())?,
+            destination: track!(r.node_id())?,
+            seq_no: SequenceNumber::new(track!(r.u64())?),
+            term: Term::new(track!(r.u64())?),
+        };
+        let message = match tag {
+            0 => RequestVoteCall {
+                header,
+                log_tail: track!(r.position())?,
+            }
+            .into(),
+            1 => RequestVoteReply {
+                header,
+                voted: track!(r.bool())?,
+            }
+            .into(),
+            2 => {
+                let committed_log_tail = LogIndex::new(track!(r.u64())?);
+                let head = track!(r.position())?;
+                let count = track!(r.u64())?;
+                let entries = track!((0..count).map(|_| r.entry()).collect::<Result<_>>())?;
+                AppendEntriesCall {
+                    header,
+                    committed_log_tail,
+                    suffix: LogSuffix { head, entries },
+                }
+                .into()
+            }
+            3 => {
+                let log_tail = track!(r.position())?;
+                let busy = track!(r.bool())?;
+                let mut reply = AppendEntriesReply::new(header, log_tail, busy);
+                if track!(r.bool())? {
+                    reply = reply.with_conflict(ConflictHint {
+                        term: Term::new(track!(r.u64())?),
+                        first_index: LogIndex::new(track!(r.u64())?),
+                    });
+                }
+                reply.into()
+            }
+            4 => InstallSnapshotCast {
+                header,
+                prefix: LogPrefix {
+                    tail: track!(r.position())?,
+                    config: track!(r.config())?,
+                    snapshot: track!(r.bytes())?.to_owned(),
+                },
+            }
+            .into(),
+            5 => LeaveCast { header }.into(),
+            _ => track_panic!(ErrorKind::InvalidInput, "Unknown message tag: {}", tag),
+        };
+        track_assert!(
+            r.0.is_empty(),
+            ErrorKind::InvalidInput,
+            "{} trailing bytes",
+            r.0.len()
+        );
+        Ok(message)
+    }
+
+    fn put_u64(buf: &mut Vec<u8>, n: u64) {
+        buf.extend_from_slice(&n.to_be_bytes());
+    }
+
+    fn put_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+        put_u64(buf, bytes.len() as u64);
+        buf.extend_from_slice(bytes);
+    }
+
+    fn put_position(buf: &mut Vec<u8>, position: LogPosition) {
+        put_u64(buf, position.prev_term.as_u64());
+        put_u64(buf, position.index.as_u64());
+    }
+
+    fn put_entry(buf: &mut Vec<u8>, entry: &LogEntry) {
+        match entry {
+            LogEntry::Noop { term } => {
+                buf.push(0);
+                put_u64(buf, term.as_u64());
+            }
+            LogEntry::Config { term, config } => {
+                buf.push(1);
+                put_u64(buf, term.as_u64());
+                put_config(buf, config);
+            }
+            LogEntry::Command { term, command } => {
+                buf.push(2);
+                put_u64(buf, term.as_u64());
+                put_bytes(buf, command);
+            }
+        }
+    }
+
+    fn put_config(buf: &mut Vec<u8>, config: &ClusterConfig) {
+        buf.push(match config.state() {
+            ClusterState::Stable => 0,
+            ClusterState::CatchUp => 1,
+            ClusterState::Joint => 2,
+        });
+        for members in &[config.new_members(), config.old_members()] {
+            put_u64(buf, members.len() as u64);
+            for id in members.iter() {
+                put_bytes(buf, id.as_str().as_bytes());
+            }
+        }
+        match config.change_index() {
+            None => buf.push(0),
+            Some(index) => {
+                buf.push(1);
+                put_u64(buf, index.as_u64());
+            }
+        }
+        match config.change_id() {
+            None => buf.push(0),
+            Some(id) => {
+                buf.push(1);
+                put_u64(buf, id.as_u64());
+            }
+        }
+        put_u64(buf, config.priorities().len() as u64);
+        for (id, &priority) in config.priorities() {
+            put_bytes(buf, id.as_str().as_bytes());
+            put_u64(buf, u64::from(priority));
+        }
+        match config.tombstones() {
+            None => buf.push(0),
+            Some(tombstones) => {
+                buf.push(1);
+                put_u64(buf, tombstones.len() as u64);
+                for (id, index) in tombstones {
+                    put_bytes(buf, id.as_str().as_bytes());
+                    put_u64(buf, index.as_u64());
+                }
+            }
+        }
+        put_u64(buf, config.explicit_learners().len() as u64);
+        for id in config.explicit_learners().iter() {
+            put_bytes(buf, id.as_str().as_bytes());
+        }
+    }
+
+    /// `encode_message` の出力を先頭から読み進める。
+    struct WireReader<'a>(&'a [u8]);
+
+    impl<'a> WireReader<'a> {
+        fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+            track_assert!(
+                n <= self.0.len(),
+                ErrorKind::InvalidInput,
+                "Unexpected end of bytes"
+            );
+            let (head, rest) = self.0.split_at(n);
+            self.0 = rest;
+            Ok(head)
+        }
+
+        fn u8(&mut self) -> Result<u8> {
+            Ok(track!(self.take(1))?[0])
+        }
+
+        fn bool(&mut self) -> Result<bool> {
+            Ok(track!(self.u8())? != 0)
+        }
+
+        fn u64(&mut self) -> Result<u64> {
+            let mut n = [0; 8];
+            n.copy_from_slice(track!(self.take(8))?);
+            Ok(u64::from_be_bytes(n))
+        }
+
+        fn bytes(&mut self) -> Result<&'a [u8]> {
+            let len = track!(self.u64())? as usize;
+            track!(self.take(len))
+        }
+
+        fn node_id(&mut self) -> Result<NodeId> {
+            let bytes = track!(self.bytes())?;
+            let id = track!(String::from_utf8(bytes.to_owned())
+                .map_err(|e| ErrorKind::InvalidInput.cause(e.to_string())))?;
+            Ok(NodeId::new(id))
+        }
+
+        fn position(&mut self) -> Result<LogPosition> {
+            Ok(LogPosition {
+                prev_term: Term::new(track!(self.u64())?),
+                index: LogIndex::new(track!(self.u64())?),
+            })
+        }
+
+        fn entry(&mut self) -> Result<LogEntry> {
+            let tag = track!(self.u8())?;
+            let term = Term::new(track!(self.u64())?);
+            Ok(match tag {
+                0 => LogEntry::Noop { term },
+                1 => LogEntry::Config {
+                    term,
+                    config: track!(self.config())?,
+                },
+                2 => LogEntry::Command {
+                    term,
+                    command: track!(self.bytes())?.to_owned(),
+                },
+                _ => track_panic!(ErrorKind::InvalidInput, "Unknown entry tag: {}", tag),
+            })
+        }
+
+        fn members(&mut self) -> Result<ClusterMembers> {
+            let count = track!(self.u64())?;
+            (0..count).map(|_| self.node_id()).collect()
+        }
+
+        fn config(&mut self) -> Result<ClusterConfig> {
+            let state = match track!(self.u8())? {
+                0 => ClusterState::Stable,
+                1 => ClusterState::CatchUp,
+                2 => ClusterState::Joint,
+                s => track_panic!(ErrorKind::InvalidInput, "Unknown cluster state: {}", s),
+            };
+            let new = track!(self.members())?;
+            let old = track!(self.members())?;
+            let mut config = ClusterConfig::with_state(new, old, state);
+            if track!(self.bool())? {
+                let index = LogIndex::new(track!(self.u64())?);
+                config = config.with_change_index(index);
+            }
+            if track!(self.bool())? {
+                let id = ChangeId::new(track!(self.u64())?);
+                config = config.with_change_id(id);
+            }
+            let count = track!(self.u64())?;
+            let priorities = track!((0..count)
+                .map(|_| Ok((track!(self.node_id())?, track!(self.u64())? as u32)))
+                .collect::<Result<_>>())?;
+            config = config.with_priorities(priorities);
+            if track!(self.bool())? {
+                let count = track!(self.u64())?;
+                let tombstones = track!((0..count)
+                    .map(|_| Ok((track!(self.node_id())?, LogIndex::new(track!(self.u64())?))))
+                    .collect::<Result<_>>())?;
+                config = config.with_tombstones(tombstones);
+            }
+            let learners = track!(self.members())?;
+            Ok(config.with_learners(learners))
         }
     }
 }